@@ -0,0 +1,117 @@
+// Lookup time of the route table as the number of routes grows.
+//
+// `distinct` routes have the shape `/resource{i}/:id/items/:item`, so the
+// first segment picks the route. `shared` routes all live under
+// `/api/v1/:tenant/` and only differ further down, next to a param route
+// at every level, which makes the lookup visit both subtrees.
+//
+// The `tree` group times the prefix tree on its own, the `router` group
+// a whole request through the router.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use nexus::{body::Body, handler::get, http::Request, router::PathTree, Router};
+use tower::{Service, ServiceExt};
+
+fn distinct(i: usize) -> (String, String) {
+    (
+        format!("/resource{}/:id/items/:item", i),
+        format!("/resource{}/42/items/7", i),
+    )
+}
+
+fn shared(i: usize) -> (String, String) {
+    (
+        format!("/api/v1/:tenant/resource{}/:id/items", i),
+        format!("/api/v1/acme/resource{}/42/items", i),
+    )
+}
+
+// routes that overlap with every `shared` route up to the last segment
+const SHARED_PARAMS: &[&str] = &["/api/v1/:tenant/:kind/:id", "/api/:version/:tenant"];
+
+struct Shape {
+    name: &'static str,
+    // pattern and matching uri of the `i`th route
+    route: fn(usize) -> (String, String),
+    extra: &'static [&'static str],
+}
+
+const SHAPES: [Shape; 2] = [
+    Shape {
+        name: "distinct",
+        route: distinct,
+        extra: &[],
+    },
+    Shape {
+        name: "shared",
+        route: shared,
+        extra: SHARED_PARAMS,
+    },
+];
+const SIZES: [usize; 4] = [10, 100, 300, 1000];
+
+fn routes(n: usize, shape: &Shape) -> Vec<String> {
+    (0..n)
+        .map(|i| (shape.route)(i).0)
+        .chain(shape.extra.iter().map(|route| route.to_string()))
+        .collect()
+}
+
+fn router_with_routes(routes: &[String]) -> impl Service<Request<Body>> + Clone {
+    routes.iter().fold(Router::new(), |router, route| {
+        router.route(route, get(|| async { "ok" }))
+    })
+}
+
+fn tree(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tree");
+
+    for shape in &SHAPES {
+        for n in SIZES {
+            let mut tree = PathTree::default();
+            for route in routes(n, shape) {
+                tree.insert(&route);
+            }
+
+            for (name, i) in [("first", 0), ("last", n - 1)] {
+                let uri = (shape.route)(i).1;
+                let id = BenchmarkId::new(format!("{}/{}", shape.name, name), n);
+
+                group.bench_with_input(id, &uri, |b, uri| b.iter(|| tree.at(uri)));
+            }
+        }
+    }
+
+    group.finish();
+}
+
+fn router(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("router");
+
+    for shape in &SHAPES {
+        for n in SIZES {
+            let router = router_with_routes(&routes(n, shape));
+
+            for (name, i) in [("first", 0), ("last", n - 1)] {
+                let uri = (shape.route)(i).1;
+                let id = BenchmarkId::new(format!("{}/{}", shape.name, name), n);
+
+                group.bench_with_input(id, &uri, |b, uri| {
+                    b.iter(|| {
+                        let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+                        rt.block_on(router.clone().oneshot(req))
+                    })
+                });
+            }
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, tree, router);
+criterion_main!(benches);
@@ -1,9 +1,27 @@
-use http::header::USER_AGENT;
+use futures_util::{stream, StreamExt};
+use headers::{authorization::Bearer, Authorization};
+use http::{Request, Response, StatusCode};
+use nexus::{body::BoxBody, middleware::Next};
 use nexus::{
-    extract::builtin::{query::Query, typed_header::TypedHeader},
-    response::IntoResponse,
+    extract::builtin::{
+        connect_info::ConnectInfo,
+        content_length_limit::ContentLengthLimit,
+        json::Json,
+        multipart::{Multipart, MultipartError},
+        path::Path,
+        peer_certificates::PeerCertificates,
+        query::Query,
+        typed_header::TypedHeader,
+        ws::{Message, WebSocketUpgrade},
+    },
+    extract::request_parts::OriginalUri,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::{convert::Infallible, net::SocketAddr, time::Duration};
 use tracing::info;
 pub async fn type_handler(user_agent: TypedHeader<headers::UserAgent>) -> impl IntoResponse {
     let url = "localhost";
@@ -32,7 +50,192 @@ pub struct Pagination {
 pub async fn page_handler(pagination: Query<Pagination>) -> &'static str {
     let pagination = pagination.0;
 
-    info!(?pagination, "Got a connection!");
+    info!(
+        page = pagination.page,
+        per_page = pagination.per_page,
+        "Got a connection!"
+    );
 
     "<h1> Hello, World!<h1>"
 }
+
+#[derive(Debug, Deserialize)]
+pub struct CreateUser {
+    name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct User {
+    id: u64,
+    name: String,
+}
+
+pub async fn json_handler(Json(payload): Json<CreateUser>) -> Json<User> {
+    Json(User {
+        id: 1,
+        name: payload.name,
+    })
+}
+
+// large enough to be worth compressing
+pub async fn list_users_handler() -> Json<Vec<User>> {
+    let users = (1..=1000)
+        .map(|id| User {
+            id,
+            name: format!("nexus-{}", id),
+        })
+        .collect();
+    Json(users)
+}
+
+pub async fn user_handler(Path(id): Path<u64>) -> Json<User> {
+    Json(User {
+        id,
+        name: "nexus".to_string(),
+    })
+}
+
+pub async fn upload_handler(
+    ContentLengthLimit(body): ContentLengthLimit<nexus::body::Bytes, 1024>,
+) -> String {
+    format!("Received {} bytes", body.len())
+}
+
+pub async fn versioned_user_handler(
+    Path((version, id)): Path<(String, u64)>,
+    OriginalUri(uri): OriginalUri,
+) -> String {
+    format!("{} user {} (requested {})", version, id, uri)
+}
+
+pub async fn assets_handler(Path(path): Path<String>) -> String {
+    format!("Serving asset `{}`", path)
+}
+
+#[derive(Debug, Serialize)]
+pub struct NotFound {
+    error: &'static str,
+    path: String,
+}
+
+pub async fn not_found_handler(uri: http::Uri) -> impl IntoResponse {
+    (
+        http::StatusCode::NOT_FOUND,
+        Json(NotFound {
+            error: "not found",
+            path: uri.path().to_string(),
+        }),
+    )
+}
+
+pub async fn slow_handler() -> &'static str {
+    tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+    "Finally done"
+}
+
+// turn middleware errors into responses, timeouts become 408
+pub fn handle_timeout(
+    err: nexus::BoxError,
+) -> Result<(http::StatusCode, String), std::convert::Infallible> {
+    if err.is::<tower::timeout::error::Elapsed>() {
+        Ok((
+            http::StatusCode::REQUEST_TIMEOUT,
+            "Request took too long".to_string(),
+        ))
+    } else {
+        Ok((
+            http::StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Unhandled internal error: {}", err),
+        ))
+    }
+}
+
+// echo the client address back
+pub async fn whoami_handler(ConnectInfo(addr): ConnectInfo<SocketAddr>) -> String {
+    info!(%addr, "whoami");
+    addr.to_string()
+}
+
+// number of certificates the client presented, only served over TLS
+pub async fn peer_handler(certs: PeerCertificates) -> String {
+    format!("client presented {} certificate(s)", certs.len())
+}
+
+// echo text and binary messages back to the client
+pub async fn ws_handler(ws: WebSocketUpgrade) -> impl IntoResponse {
+    ws.protocols(["echo"])
+        .max_message_size(64 * 1024)
+        .on_upgrade(|mut socket| async move {
+            while let Some(Ok(msg)) = socket.recv().await {
+                if matches!(msg, Message::Text(_) | Message::Binary(_))
+                    && socket.send(msg).await.is_err()
+                {
+                    break;
+                }
+            }
+        })
+}
+
+#[derive(Serialize)]
+struct Tick {
+    tick: u64,
+}
+
+// push a tick every second, a keep-alive comment is sent while the client
+// waits longer than that
+pub async fn sse_handler() -> impl IntoResponse {
+    let hello = Event::default()
+        .event("hello")
+        .data("live updates\nstarting");
+    let ticks = stream::unfold(0, |tick| async move {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let event = Event::default()
+            .event("tick")
+            .id(tick.to_string())
+            .json_data(Tick { tick })
+            .unwrap();
+        Some((event, tick + 1))
+    });
+    let events = stream::iter([hello]).chain(ticks).map(Ok::<_, Infallible>);
+
+    Sse::new(events).keep_alive(KeepAlive::new().interval(Duration::from_millis(400)))
+}
+
+// report the fields of a multipart upload, files are read in chunks and
+// limited to 1 MiB each
+pub async fn form_upload_handler(
+    mut multipart: Multipart<{ 4 * 1024 * 1024 }, { 1024 * 1024 }>,
+) -> Result<String, MultipartError> {
+    let mut report = String::new();
+    while let Some(mut field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or_default().to_owned();
+        match field.file_name().map(ToOwned::to_owned) {
+            Some(file_name) => {
+                let content_type = field.content_type().unwrap_or_default().to_owned();
+                let mut size = 0;
+                while let Some(chunk) = field.chunk().await? {
+                    size += chunk.len();
+                }
+                report += &format!("{name}: file `{file_name}` ({content_type}), {size} bytes\n");
+            }
+            None => report += &format!("{name}: {}\n", field.text().await?),
+        }
+    }
+    Ok(report)
+}
+
+// only let requests with the demo token through
+pub async fn require_token<B>(
+    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response<BoxBody>, StatusCode> {
+    if auth.token() != "secret" {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(next.run(req).await)
+}
+
+pub async fn admin_handler() -> &'static str {
+    "welcome, admin"
+}
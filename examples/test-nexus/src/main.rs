@@ -1,47 +1,158 @@
 // Design the APIs
-use std::net::SocketAddr;
+use std::{net::SocketAddr, time::Duration};
 
 use color_eyre::Report;
-use http::header::USER_AGENT;
+use http::header::{CACHE_CONTROL, CONTENT_TYPE, USER_AGENT};
 use hyper::Body;
 use nexus::{
     self,
-    handler::{get, post},
+    body::BoxBody,
+    handler::{get, post, Handler},
+    middleware::{
+        self, compression::CompressionLayer, trace::TraceRequestBody, AllowOrigin, CorsLayer,
+        Metrics, TraceLayer,
+    },
+    router::method_filter::MethodFilter,
+    serve::tls::RustlsConfig,
     Router,
 };
-use tower_http::set_header::SetRequestHeaderLayer;
-use tracing::info;
+use tower::timeout::TimeoutLayer;
+use tower_http::set_header::{SetRequestHeaderLayer, SetResponseHeaderLayer};
+use tracing::{info, warn};
 use tracing_subscriber::EnvFilter;
 
 mod handlers;
 use headers::HeaderValue;
 
-use crate::handlers::{handler, page_handler, type_handler};
+use crate::handlers::{
+    admin_handler, assets_handler, form_upload_handler, handle_timeout, handler, json_handler,
+    list_users_handler, not_found_handler, page_handler, peer_handler, require_token, slow_handler,
+    sse_handler, type_handler, upload_handler, user_handler, versioned_user_handler,
+    whoami_handler, ws_handler,
+};
 
 #[tokio::main]
 async fn main() -> Result<(), Report> {
     setup()?;
     info!("nexus init...");
     // build application with a route
+    let api = Router::new().route("/users/:id", get(versioned_user_handler));
+    let metrics = Metrics::new();
+
     let app = Router::new()
-        // .route("/", post(handler))
-        .route("/", get(type_handler))
-        .route("/page", get(page_handler))
-        .layer(SetRequestHeaderLayer::<_, Body>::overriding(
-            USER_AGENT,
-            HeaderValue::from_static("nexus-http demo"),
-        ));
+        .route("/", get(type_handler).post(handler))
+        .route(
+            "/page",
+            get(
+                page_handler.layer(SetResponseHeaderLayer::<_, BoxBody>::overriding(
+                    CACHE_CONTROL,
+                    HeaderValue::from_static("no-cache"),
+                )),
+            ),
+        )
+        .route("/users", get(list_users_handler).post(json_handler))
+        .route("/users/:id", get(user_handler))
+        .route("/upload", post(upload_handler))
+        .route("/whoami", get(whoami_handler))
+        .route("/peer", get(peer_handler))
+        .route("/ws", get(ws_handler))
+        .route("/sse", get(sse_handler))
+        .route("/form-upload", post(form_upload_handler))
+        .route("/metrics", get(metrics.handler()))
+        .route(
+            "/admin",
+            get(admin_handler.layer(middleware::from_fn(require_token))),
+        )
+        .nest("/api/:version", api)
+        .route("/assets/*path", get(assets_handler))
+        .route(
+            "/slow",
+            get(slow_handler
+                .layer(TimeoutLayer::new(Duration::from_secs(1)))
+                .handle_error(handle_timeout)),
+        )
+        .fallback_handler(not_found_handler)
+        .auto_options()
+        .layer(
+            SetRequestHeaderLayer::<_, TraceRequestBody<Body>>::overriding(
+                USER_AGENT,
+                HeaderValue::from_static("nexus-http demo"),
+            ),
+        )
+        .layer(TimeoutLayer::new(Duration::from_secs(10)))
+        .handle_error(handle_timeout)
+        .layer(
+            CorsLayer::new()
+                .allow_origin(AllowOrigin::exact(HeaderValue::from_static(
+                    "http://localhost:8080",
+                )))
+                .allow_methods(MethodFilter::GET | MethodFilter::POST)
+                .allow_headers([CONTENT_TYPE])
+                .max_age(Duration::from_secs(600)),
+        )
+        .layer(CompressionLayer::new())
+        .layer(TraceLayer::new().on_finished(|finished, _span| {
+            if finished.latency() > Duration::from_secs(1) {
+                warn!(route = ?finished.route(), latency = ?finished.latency(), "slow request");
+            }
+        }))
+        .layer(metrics.layer())
+        .check_infallible();
 
     let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
     info!(%addr,"Listening on: {}",addr);
 
-    nexus::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await?;
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    let server = nexus::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .http2_max_concurrent_streams(256)
+    .with_graceful_shutdown(shutdown_signal())
+    .drain_timeout(Duration::from_secs(30));
+
+    // serve HTTPS when a certificate is configured
+    match (
+        std::env::var("NEXUS_TLS_CERT"),
+        std::env::var("NEXUS_TLS_KEY"),
+    ) {
+        (Ok(cert), Ok(key)) => {
+            let config = RustlsConfig::from_pem_file(cert, key)?;
+            server.tls(config).await?
+        }
+        _ => server.await?,
+    }
 
     Ok(())
 }
 
+// completes on ctrl-c, or SIGTERM on unix
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install ctrl-c handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    info!("shutdown signal received, draining connections");
+}
+
 fn setup() -> Result<(), Report> {
     if std::env::var("RUST_LIB_BACKTRACE").is_err() {
         std::env::set_var("RUST_LIB_BACKTRACE", "1")
@@ -125,7 +125,7 @@ impl<B> RequestParts<B> {
     }
 
     pub fn headers_mut(&mut self) -> Option<&mut HeaderMap> {
-        self.headers_mut()
+        self.headers.as_mut()
     }
 
     pub fn take_headers(&mut self) -> Option<HeaderMap> {
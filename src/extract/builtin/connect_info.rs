@@ -0,0 +1,224 @@
+// Information about the connection a request came in on, e.g. the peer
+// address.
+//
+// `IntoMakeServiceWithConnectInfo` runs once per connection, computes the
+// value from the connection target with `Connected` and adds it to the
+// extensions of every request on that connection.
+
+use std::{
+    convert::Infallible,
+    fmt,
+    future::{ready, Ready},
+    marker::PhantomData,
+    net::SocketAddr,
+    ops::Deref,
+    task::{Context, Poll},
+};
+
+use async_trait::async_trait;
+use hyper::server::conn::AddrStream;
+use tower_http::add_extension::AddExtension;
+use tower_service::Service;
+
+use crate::extract::{
+    rejection::{ConnectInfoRejection, ExtensionAlreadyExtracted, MissingConnectInfo},
+    FromRequest, RequestParts,
+};
+
+// Types that can be created from a connection target. Implement it for your
+// own type to extract more than the peer address.
+pub trait Connected<T>: Clone + Send + Sync + 'static {
+    fn connect_info(target: T) -> Self;
+}
+
+impl Connected<&AddrStream> for SocketAddr {
+    fn connect_info(target: &AddrStream) -> Self {
+        target.remote_addr()
+    }
+}
+
+pub struct IntoMakeServiceWithConnectInfo<S, C> {
+    svc: S,
+    _connect_info: PhantomData<fn() -> C>,
+}
+
+impl<S, C> IntoMakeServiceWithConnectInfo<S, C> {
+    pub(crate) fn new(svc: S) -> Self {
+        Self {
+            svc,
+            _connect_info: PhantomData,
+        }
+    }
+}
+
+impl<S, C> Clone for IntoMakeServiceWithConnectInfo<S, C>
+where
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Self::new(self.svc.clone())
+    }
+}
+
+impl<S, C> fmt::Debug for IntoMakeServiceWithConnectInfo<S, C>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IntoMakeServiceWithConnectInfo")
+            .field("svc", &self.svc)
+            .field("connect_info", &std::any::type_name::<C>())
+            .finish()
+    }
+}
+
+impl<S, C, T> Service<T> for IntoMakeServiceWithConnectInfo<S, C>
+where
+    S: Clone,
+    C: Connected<T>,
+{
+    type Response = AddExtension<S, ConnectInfo<C>>;
+    type Error = Infallible;
+    type Future = MakeServiceWithConnectInfoFuture<S, C>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let connect_info = ConnectInfo(C::connect_info(target));
+        let svc = AddExtension::new(self.svc.clone(), connect_info);
+
+        MakeServiceWithConnectInfoFuture {
+            future: ready(Ok(svc)),
+        }
+    }
+}
+
+opaque_future! {
+    pub type MakeServiceWithConnectInfoFuture<S, C> =
+        Ready<Result<AddExtension<S, ConnectInfo<C>>, Infallible>>;
+}
+
+// Extractor for the value added by `into_make_service_with_connect_info`.
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectInfo<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for ConnectInfo<T>
+where
+    T: Clone + Send + Sync + 'static,
+    B: Send,
+{
+    type Rejection = ConnectInfoRejection;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let connect_info = req
+            .extensions()
+            .ok_or(ExtensionAlreadyExtracted)?
+            .get::<Self>()
+            .cloned()
+            .ok_or(MissingConnectInfo)?;
+
+        Ok(connect_info)
+    }
+}
+
+impl<T> Deref for ConnectInfo<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::IntoFuture;
+
+    use http::{Request, StatusCode};
+    use hyper::Body;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{handler::get, response::IntoResponse, serve, Router};
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Peer(String);
+
+    impl Connected<&str> for Peer {
+        fn connect_info(target: &str) -> Self {
+            Peer(target.to_owned())
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_connect_info() {
+        let mut req = RequestParts::new(Request::new(Body::empty()));
+        let rejection = ConnectInfo::<SocketAddr>::from_request(&mut req)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            rejection,
+            ConnectInfoRejection::MissingConnectInfo(_)
+        ));
+
+        let res = rejection.into_response();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(
+            body,
+            "Missing connection info, serve the app with `into_make_service_with_connect_info`"
+        );
+    }
+
+    #[tokio::test]
+    async fn custom_connected() {
+        let app = Router::new().route(
+            "/",
+            get(|ConnectInfo(peer): ConnectInfo<Peer>| async move { peer.0 }),
+        );
+
+        let svc = app
+            .into_make_service_with_connect_info::<Peer>()
+            .oneshot("client-1")
+            .await
+            .unwrap();
+        let res = svc.oneshot(Request::new(Body::empty())).await.unwrap();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body, "client-1");
+    }
+
+    #[tokio::test]
+    async fn serves_peer_addr() {
+        let app = Router::new().route(
+            "/",
+            get(|ConnectInfo(addr): ConnectInfo<SocketAddr>| async move { addr.to_string() }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .into_future(),
+        );
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let peer = stream.local_addr().unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await.unwrap();
+
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{}", res);
+        assert!(res.ends_with(&format!("\r\n\r\n{}", peer)), "{}", res);
+    }
+}
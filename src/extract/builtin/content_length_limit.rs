@@ -0,0 +1,189 @@
+use std::ops::Deref;
+
+use async_trait::async_trait;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use http::header;
+use http_body::Body as _;
+
+use crate::{
+    extract::{
+        rejection::{
+            ContentLengthLimitRejection, FailedToBufferBody, HeadersAlreadyExtracted,
+            PayloadTooLarge,
+        },
+        FromRequest, RequestParts,
+    },
+    BoxError,
+};
+
+// Extractor that rejects request bodies larger than `N` bytes before the
+// inner extractor `T` runs.
+//
+// A `Content-Length` over the limit is rejected without reading the body.
+// Without the header (chunked uploads) the body is read up to the limit and
+// the request is rejected as soon as it goes over, so at most `N` bytes are
+// ever buffered.
+#[derive(Debug, Clone)]
+pub struct ContentLengthLimit<T, const N: u64>(pub T);
+
+#[async_trait]
+impl<T, B, const N: u64> FromRequest<B> for ContentLengthLimit<T, N>
+where
+    T: FromRequest<B>,
+    B: http_body::Body + From<Bytes> + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = ContentLengthLimitRejection<T::Rejection>;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let content_length = req
+            .headers()
+            .ok_or(ContentLengthLimitRejection::HeadersAlreadyExtracted(
+                HeadersAlreadyExtracted,
+            ))?
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+
+        match content_length {
+            Some(length) if length > N => {
+                return Err(ContentLengthLimitRejection::PayloadTooLarge(
+                    PayloadTooLarge,
+                ));
+            }
+            Some(_) => {}
+            None => {
+                // let the inner extractor report a missing body
+                if let Some(body) = req.body.take() {
+                    let bytes = read_limited(body, N).await?;
+                    req.body = Some(B::from(bytes));
+                }
+            }
+        }
+
+        let value = T::from_request(req)
+            .await
+            .map_err(ContentLengthLimitRejection::Inner)?;
+
+        Ok(Self(value))
+    }
+}
+
+async fn read_limited<B, T>(body: B, limit: u64) -> Result<Bytes, ContentLengthLimitRejection<T>>
+where
+    B: http_body::Body,
+    B::Error: Into<BoxError>,
+{
+    let mut body = Box::pin(body);
+    let mut buf = BytesMut::new();
+
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| {
+            ContentLengthLimitRejection::FailedToBufferBody(FailedToBufferBody::from_err(err))
+        })?;
+
+        if (buf.len() + chunk.remaining()) as u64 > limit {
+            return Err(ContentLengthLimitRejection::PayloadTooLarge(
+                PayloadTooLarge,
+            ));
+        }
+
+        buf.put(chunk);
+    }
+
+    Ok(buf.freeze())
+}
+
+impl<T, const N: u64> Deref for ContentLengthLimit<T, N> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::stream;
+    use http::{Request, StatusCode};
+    use hyper::Body;
+
+    use super::*;
+    use crate::response::IntoResponse;
+
+    type Limited = ContentLengthLimit<Bytes, 8>;
+
+    async fn extract(
+        content_length: Option<u64>,
+        body: Body,
+    ) -> Result<Limited, ContentLengthLimitRejection<crate::extract::rejection::BytesRejection>>
+    {
+        let mut req = Request::builder();
+        if let Some(content_length) = content_length {
+            req = req.header(header::CONTENT_LENGTH, content_length);
+        }
+        let mut req = RequestParts::new(req.body(body).unwrap());
+        Limited::from_request(&mut req).await
+    }
+
+    fn chunked(chunks: &'static [&'static str]) -> Body {
+        Body::wrap_stream(stream::iter(
+            chunks.iter().map(|chunk| Ok::<_, BoxError>(*chunk)),
+        ))
+    }
+
+    #[tokio::test]
+    async fn within_limit() {
+        let ContentLengthLimit(bytes) = extract(Some(5), Body::from("hello")).await.unwrap();
+        assert_eq!(bytes, "hello");
+
+        let ContentLengthLimit(bytes) = extract(None, chunked(&["hel", "lo", "!"])).await.unwrap();
+        assert_eq!(bytes, "hello!");
+
+        let ContentLengthLimit(bytes) = extract(None, chunked(&["1234", "5678"])).await.unwrap();
+        assert_eq!(bytes, "12345678");
+    }
+
+    #[tokio::test]
+    async fn content_length_over_limit() {
+        // the body would fail if it were read
+        let body = Body::wrap_stream(stream::iter(vec![Err::<Bytes, BoxError>("read".into())]));
+        let rejection = extract(Some(9), body).await.unwrap_err();
+        assert!(matches!(
+            rejection,
+            ContentLengthLimitRejection::PayloadTooLarge(_)
+        ));
+        assert_eq!(
+            rejection.into_response().status(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+
+    #[tokio::test]
+    async fn chunked_over_limit() {
+        let rejection = extract(None, chunked(&["1234", "5678", "9"]))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            rejection,
+            ContentLengthLimitRejection::PayloadTooLarge(_)
+        ));
+        assert_eq!(
+            rejection.into_response().status(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+
+    #[tokio::test]
+    async fn body_fails() {
+        let body = Body::wrap_stream(stream::iter(vec![
+            Ok(Bytes::from("12")),
+            Err::<Bytes, BoxError>("connection reset".into()),
+        ]));
+        let rejection = extract(None, body).await.unwrap_err();
+        assert!(matches!(
+            rejection,
+            ContentLengthLimitRejection::FailedToBufferBody(_)
+        ));
+    }
+}
@@ -0,0 +1,112 @@
+use std::ops::Deref;
+
+use async_trait::async_trait;
+
+use crate::extract::{
+    rejection::{ExtensionAlreadyExtracted, ExtensionRejection, MissingExtension},
+    FromRequest, RequestParts,
+};
+
+// Extractor for shared state inserted by `AddExtensionLayer`.
+//
+// A missing extension is a server configuration error, so the rejection
+// is a `500` naming the type that was requested.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Extension<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for Extension<T>
+where
+    T: Clone + Send + Sync + 'static,
+    B: Send,
+{
+    type Rejection = ExtensionRejection;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let value = req
+            .extensions()
+            .ok_or(ExtensionAlreadyExtracted)?
+            .get::<T>()
+            .cloned()
+            .ok_or_else(|| {
+                MissingExtension::from_err(format!(
+                    "Extension of type `{}` was not found. Perhaps you forgot to add it with `AddExtensionLayer`?",
+                    std::any::type_name::<T>()
+                ))
+            })?;
+
+        Ok(Extension(value))
+    }
+}
+
+impl<T> Deref for Extension<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use http::{Request, StatusCode};
+    use hyper::Body;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{handler::get, response::IntoResponse, AddExtensionLayer, Router};
+
+    #[derive(Debug, Clone)]
+    struct State {
+        hits: Arc<AtomicUsize>,
+    }
+
+    #[tokio::test]
+    async fn missing_extension() {
+        let mut req = RequestParts::new(Request::new(Body::empty()));
+        let rejection = Extension::<State>::from_request(&mut req)
+            .await
+            .unwrap_err();
+        assert!(matches!(rejection, ExtensionRejection::MissingExtension(_)));
+
+        let res = rejection.into_response();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.starts_with("Missing request extension: "), "{}", body);
+        assert!(body.contains(std::any::type_name::<State>()), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn state_from_layer() {
+        let state = State {
+            hits: Arc::new(AtomicUsize::new(0)),
+        };
+        let app = Router::new()
+            .route(
+                "/",
+                get(|Extension(state): Extension<State>| async move {
+                    let hits = state.hits.fetch_add(1, Ordering::SeqCst) + 1;
+                    hits.to_string()
+                }),
+            )
+            .layer(AddExtensionLayer::new(state.clone()));
+
+        for expected in ["1", "2"] {
+            let res = app
+                .clone()
+                .oneshot(Request::new(Body::empty()))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert_eq!(body, expected);
+        }
+        assert_eq!(state.hits.load(Ordering::SeqCst), 2);
+    }
+}
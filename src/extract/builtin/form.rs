@@ -0,0 +1,206 @@
+use std::{convert::Infallible, ops::Deref};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{header, HeaderValue, Method, Response, StatusCode};
+use http_body::Full;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    extract::{
+        has_content_type,
+        rejection::{
+            FailedToBufferBody, FailedToDeserializeQueryString, FormRejection,
+            InvalidFormContentType,
+        },
+        take_body, FromRequest, RequestParts,
+    },
+    response::IntoResponse,
+    BoxError,
+};
+
+// Urlencoded form extractor and response.
+//
+// `GET` and `HEAD` requests are read from the query string, every other
+// method must send an `application/x-www-form-urlencoded` body.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Form<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for Form<T>
+where
+    T: DeserializeOwned,
+    B: http_body::Body + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = FormRejection;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        if req.method() == Method::GET || req.method() == Method::HEAD {
+            let query = req.uri().query().unwrap_or_default();
+            let value = serde_urlencoded::from_str(query)
+                .map_err(FailedToDeserializeQueryString::new::<T, _>)?;
+            return Ok(Form(value));
+        }
+
+        if !has_content_type(req, "application/x-www-form-urlencoded")? {
+            return Err(InvalidFormContentType.into());
+        }
+
+        let body = take_body(req)?;
+
+        let bytes = hyper::body::to_bytes(body)
+            .await
+            .map_err(FailedToBufferBody::from_err)?;
+
+        let value = serde_urlencoded::from_bytes(&bytes)
+            .map_err(FailedToDeserializeQueryString::new::<T, _>)?;
+
+        Ok(Form(value))
+    }
+}
+
+impl<T> Deref for Form<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> IntoResponse for Form<T>
+where
+    T: Serialize,
+{
+    type Body = Full<Bytes>;
+    type BodyError = Infallible;
+
+    fn into_response(self) -> Response<Self::Body> {
+        let body = match serde_urlencoded::to_string(&self.0) {
+            Ok(body) => body,
+            Err(err) => {
+                let mut res = err.to_string().into_response();
+                *res.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                return res;
+            }
+        };
+
+        let mut res = Response::new(Full::from(body));
+        res.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Request;
+    use hyper::Body;
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Search {
+        q: String,
+        page: u32,
+    }
+
+    async fn extract(
+        method: Method,
+        uri: &str,
+        content_type: Option<&str>,
+        body: &'static str,
+    ) -> Result<Form<Search>, FormRejection> {
+        let mut req = Request::builder().method(method).uri(uri);
+        if let Some(content_type) = content_type {
+            req = req.header(header::CONTENT_TYPE, content_type);
+        }
+        let mut req = RequestParts::new(req.body(Body::from(body)).unwrap());
+        Form::<Search>::from_request(&mut req).await
+    }
+
+    #[tokio::test]
+    async fn get_and_head_read_the_query() {
+        for method in [Method::GET, Method::HEAD] {
+            // the body and content type are ignored
+            let Form(search) = extract(method, "/?q=rust+lang&page=2", None, "q=body&page=9")
+                .await
+                .unwrap();
+            assert_eq!(
+                search,
+                Search {
+                    q: "rust lang".to_owned(),
+                    page: 2
+                }
+            );
+        }
+
+        let rejection = extract(Method::GET, "/?q=rust", None, "")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            rejection,
+            FormRejection::FailedToDeserializeQueryString(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn post_reads_the_body() {
+        let content_type = Some("application/x-www-form-urlencoded");
+        let Form(search) = extract(Method::POST, "/?page=9", content_type, "q=a%26b&page=3")
+            .await
+            .unwrap();
+        assert_eq!(
+            search,
+            Search {
+                q: "a&b".to_owned(),
+                page: 3
+            }
+        );
+
+        let rejection = extract(Method::POST, "/", content_type, "q=a&page=x")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            rejection,
+            FormRejection::FailedToDeserializeQueryString(_)
+        ));
+        assert_eq!(rejection.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn post_requires_form_content_type() {
+        for content_type in [None, Some("application/json")] {
+            let rejection = extract(Method::POST, "/", content_type, "q=a&page=1")
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                rejection,
+                FormRejection::InvalidFormContentType(_)
+            ));
+
+            let res = rejection.into_response();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn response() {
+        let res = Form(Search {
+            q: "a b&c".to_owned(),
+            page: 1,
+        })
+        .into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers()[header::CONTENT_TYPE],
+            "application/x-www-form-urlencoded"
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body, "q=a+b%26c&page=1");
+    }
+}
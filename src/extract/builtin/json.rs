@@ -0,0 +1,190 @@
+use std::{convert::Infallible, ops::Deref};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{header, HeaderValue, Response, StatusCode};
+use http_body::Full;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    extract::{
+        has_content_type,
+        rejection::{FailedToBufferBody, InvalidJsonBody, JsonRejection, MissingJsonContentType},
+        take_body, FromRequest, RequestParts,
+    },
+    response::IntoResponse,
+    BoxError,
+};
+
+// JSON extractor and response.
+//
+// As an extractor it requires `Content-Type: application/json` and
+// deserializes the buffered body, as a response it serializes the value
+// and sets the content type.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for Json<T>
+where
+    T: DeserializeOwned,
+    B: http_body::Body + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = JsonRejection;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        if !has_content_type(req, "application/json")? {
+            return Err(MissingJsonContentType.into());
+        }
+
+        let body = take_body(req)?;
+
+        let bytes = hyper::body::to_bytes(body)
+            .await
+            .map_err(FailedToBufferBody::from_err)?;
+
+        let value = serde_json::from_slice(&bytes).map_err(InvalidJsonBody::from_err)?;
+
+        Ok(Json(value))
+    }
+}
+
+impl<T> Deref for Json<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> From<T> for Json<T> {
+    fn from(inner: T) -> Self {
+        Self(inner)
+    }
+}
+
+impl<T> IntoResponse for Json<T>
+where
+    T: Serialize,
+{
+    type Body = Full<Bytes>;
+    type BodyError = Infallible;
+
+    fn into_response(self) -> Response<Self::Body> {
+        let bytes = match serde_json::to_vec(&self.0) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                let mut res = err.to_string().into_response();
+                *res.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                return res;
+            }
+        };
+
+        let mut res = Response::new(Full::from(bytes));
+        res.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::stream;
+    use http::Request;
+    use hyper::Body;
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+    }
+
+    async fn extract(content_type: Option<&str>, body: Body) -> Result<Json<User>, JsonRejection> {
+        let mut req = Request::builder();
+        if let Some(content_type) = content_type {
+            req = req.header(header::CONTENT_TYPE, content_type);
+        }
+        let mut req = RequestParts::new(req.body(body).unwrap());
+        Json::<User>::from_request(&mut req).await
+    }
+
+    async fn into_parts(rejection: JsonRejection) -> (StatusCode, String) {
+        let res = rejection.into_response();
+        let status = res.status();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn extracts_json() {
+        let body = Body::from(r#"{"name":"ferris"}"#);
+        let Json(user) = extract(Some("application/json"), body).await.unwrap();
+        assert_eq!(user.name, "ferris");
+
+        let body = Body::from(r#"{"name":"ferris"}"#);
+        let charset = Some("application/json; charset=utf-8");
+        assert!(extract(charset, body).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn requires_json_content_type() {
+        for content_type in [None, Some("text/plain")] {
+            let body = Body::from(r#"{"name":"ferris"}"#);
+            let rejection = extract(content_type, body).await.unwrap_err();
+            assert!(matches!(
+                rejection,
+                JsonRejection::MissingJsonContentType(_)
+            ));
+            let (status, _) = into_parts(rejection).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_json() {
+        for body in [r#"{"name":"#, r#"{"id":1}"#, ""] {
+            let rejection = extract(Some("application/json"), Body::from(body))
+                .await
+                .unwrap_err();
+            assert!(matches!(rejection, JsonRejection::InvalidJsonBody(_)));
+            let (status, body) = into_parts(rejection).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert!(body.starts_with("Failed to parse the request body as JSON: "));
+        }
+    }
+
+    #[tokio::test]
+    async fn body_fails() {
+        let body = Body::wrap_stream(stream::iter([
+            Ok(r#"{"name""#),
+            Err(std::io::Error::other("connection reset")),
+        ]));
+        let rejection = extract(Some("application/json"), body).await.unwrap_err();
+        assert!(matches!(rejection, JsonRejection::FailedToBufferBody(_)));
+        let (status, body) = into_parts(rejection).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.starts_with("Failed to buffer the request body: "));
+    }
+
+    #[tokio::test]
+    async fn response_round_trip() {
+        let user = User {
+            name: "ferris".to_owned(),
+        };
+        let res = Json(&user).into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "application/json");
+
+        let (parts, body) = res.into_parts();
+        let body = Body::from(hyper::body::to_bytes(body).await.unwrap());
+        let content_type = parts.headers[header::CONTENT_TYPE].to_str().unwrap();
+        let Json(extracted) = extract(Some(content_type), body).await.unwrap();
+        assert_eq!(extracted, user);
+    }
+}
@@ -1,4 +1,27 @@
+pub mod connect_info;
+pub mod content_length_limit;
+pub mod extension;
+pub mod form;
+pub mod json;
+#[cfg(feature = "multipart")]
+pub mod multipart;
+pub mod path;
+#[cfg(feature = "tls")]
+pub mod peer_certificates;
 pub mod query;
 pub mod typed_header;
+#[cfg(feature = "ws")]
+pub mod ws;
 
-pub use self::{query::Query, typed_header::TypedHeader};
+pub use self::{
+    connect_info::ConnectInfo, content_length_limit::ContentLengthLimit, extension::Extension,
+    form::Form, json::Json, path::Path, query::Query, typed_header::TypedHeader,
+};
+
+#[cfg(feature = "multipart")]
+pub use self::multipart::Multipart;
+#[cfg(feature = "tls")]
+pub use self::peer_certificates::PeerCertificates;
+
+#[cfg(feature = "ws")]
+pub use self::ws::WebSocketUpgrade;
@@ -0,0 +1,348 @@
+// Extractor for `multipart/form-data` bodies.
+//
+// Parts are parsed from the `BodyStream` as it arrives, nothing is buffered
+// beyond the field currently being read. `TOTAL` limits the size of the
+// whole body and `FIELD` the size of each field, going over either fails
+// the read with `413 Payload Too Large`.
+
+use std::{
+    fmt,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::stream::Stream;
+use http::{header, HeaderMap, Response, StatusCode};
+use http_body::Full;
+
+use crate::{
+    extract::{
+        rejection::{HeadersAlreadyExtracted, InvalidBoundary, MultipartRejection},
+        request_parts::BodyStream,
+        FromRequest, RequestParts,
+    },
+    response::IntoResponse,
+    BoxError,
+};
+
+pub const DEFAULT_TOTAL_LIMIT: u64 = 10 * 1024 * 1024;
+
+#[derive(Debug)]
+pub struct Multipart<const TOTAL: u64 = DEFAULT_TOTAL_LIMIT, const FIELD: u64 = { u64::MAX }> {
+    inner: multer::Multipart<'static>,
+}
+
+#[async_trait]
+impl<B, const TOTAL: u64, const FIELD: u64> FromRequest<B> for Multipart<TOTAL, FIELD>
+where
+    B: http_body::Body + Unpin + Send + 'static,
+    B::Data: Into<Bytes>,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = MultipartRejection;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let boundary = req
+            .headers()
+            .ok_or(HeadersAlreadyExtracted)?
+            .get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .and_then(|content_type| multer::parse_boundary(content_type).ok())
+            .ok_or(InvalidBoundary)?;
+
+        let stream = BodyStream::from_request(req).await?;
+
+        let limits = multer::SizeLimit::new()
+            .whole_stream(TOTAL)
+            .per_field(FIELD);
+        let constraints = multer::Constraints::new().size_limit(limits);
+
+        Ok(Self {
+            inner: multer::Multipart::with_constraints(stream, boundary, constraints),
+        })
+    }
+}
+
+impl<const TOTAL: u64, const FIELD: u64> Multipart<TOTAL, FIELD> {
+    // The next field, or `None` after the last one. A field has to be read
+    // or dropped before the next one can be yielded.
+    pub async fn next_field(&mut self) -> Result<Option<Field<'_>>, MultipartError> {
+        let field = self.inner.next_field().await.map_err(MultipartError::new)?;
+
+        Ok(field.map(|inner| Field {
+            inner,
+            _multipart: PhantomData,
+        }))
+    }
+}
+
+// A single field. Its data is read with `bytes`, `text` or in chunks with
+// `chunk` or as a `Stream`.
+pub struct Field<'a> {
+    inner: multer::Field<'static>,
+    // borrow the multipart so only one field is read at a time
+    _multipart: PhantomData<&'a mut ()>,
+}
+
+impl<'a> Field<'a> {
+    // `name` from the `Content-Disposition` header
+    pub fn name(&self) -> Option<&str> {
+        self.inner.name()
+    }
+
+    // `filename` from the `Content-Disposition` header
+    pub fn file_name(&self) -> Option<&str> {
+        self.inner.file_name()
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.inner.content_type().map(AsRef::as_ref)
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        self.inner.headers()
+    }
+
+    pub async fn bytes(self) -> Result<Bytes, MultipartError> {
+        self.inner.bytes().await.map_err(MultipartError::new)
+    }
+
+    pub async fn text(self) -> Result<String, MultipartError> {
+        self.inner.text().await.map_err(MultipartError::new)
+    }
+
+    // the next chunk of data, `None` at the end of the field
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, MultipartError> {
+        self.inner.chunk().await.map_err(MultipartError::new)
+    }
+}
+
+impl<'a> fmt::Debug for Field<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Field")
+            .field("name", &self.name())
+            .field("file_name", &self.file_name())
+            .field("content_type", &self.content_type())
+            .finish()
+    }
+}
+
+impl<'a> Stream for Field<'a> {
+    type Item = Result<Bytes, MultipartError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner)
+            .poll_next(cx)
+            .map_err(MultipartError::new)
+    }
+}
+
+// Error while reading the parts of a multipart body, it can be returned from
+// handlers as a response.
+#[derive(Debug)]
+pub struct MultipartError {
+    inner: multer::Error,
+}
+
+impl MultipartError {
+    fn new(inner: multer::Error) -> Self {
+        Self { inner }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        status(&self.inner)
+    }
+}
+
+fn status(err: &multer::Error) -> StatusCode {
+    match err {
+        multer::Error::FieldSizeExceeded { .. } | multer::Error::StreamSizeExceeded { .. } => {
+            StatusCode::PAYLOAD_TOO_LARGE
+        }
+        // the whole body limit is checked while reading the stream, a field
+        // reports it as a failed read
+        multer::Error::StreamReadFailed(source) => source
+            .downcast_ref::<multer::Error>()
+            .map_or(StatusCode::BAD_REQUEST, status),
+        _ => StatusCode::BAD_REQUEST,
+    }
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Error parsing `multipart/form-data` request: {}",
+            self.inner
+        )
+    }
+}
+
+impl std::error::Error for MultipartError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.inner)
+    }
+}
+
+impl IntoResponse for MultipartError {
+    type Body = Full<Bytes>;
+    type BodyError = std::convert::Infallible;
+
+    fn into_response(self) -> Response<Self::Body> {
+        let mut res = Response::new(Full::from(self.to_string()));
+        *res.status_mut() = self.status();
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Request;
+    use hyper::Body;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{handler::post, Router};
+
+    const BOUNDARY: &str = "X-NEXUS-BOUNDARY";
+
+    // a field as `(name, file name, data)`
+    fn form(fields: &[(&str, Option<&str>, &str)]) -> String {
+        let mut body = String::new();
+        for (name, file_name, data) in fields {
+            body += &format!("--{}\r\n", BOUNDARY);
+            match file_name {
+                Some(file_name) => {
+                    body += &format!(
+                        "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n",
+                        name, file_name
+                    );
+                    body += "Content-Type: text/plain\r\n";
+                }
+                None => body += &format!("Content-Disposition: form-data; name=\"{}\"\r\n", name),
+            }
+            body += &format!("\r\n{}\r\n", data);
+        }
+        body + &format!("--{}--\r\n", BOUNDARY)
+    }
+
+    // every field as `name[ file_name content_type]=data`
+    async fn fields<const TOTAL: u64, const FIELD: u64>(
+        mut multipart: Multipart<TOTAL, FIELD>,
+    ) -> Result<String, MultipartError> {
+        let mut fields = Vec::new();
+        while let Some(field) = multipart.next_field().await? {
+            let mut line = field.name().unwrap_or_default().to_owned();
+            if let Some(file_name) = field.file_name() {
+                line += &format!(
+                    " {} {}",
+                    file_name,
+                    field.content_type().unwrap_or_default()
+                );
+            }
+            line += &format!("={}", field.text().await?);
+            fields.push(line);
+        }
+        Ok(fields.join("\n"))
+    }
+
+    async fn send<const TOTAL: u64, const FIELD: u64>(
+        content_type: Option<&str>,
+        body: String,
+    ) -> (StatusCode, String) {
+        let app = Router::new().route("/", post(fields::<TOTAL, FIELD>));
+
+        let mut req = Request::builder().method("POST").uri("/");
+        if let Some(content_type) = content_type {
+            req = req.header(header::CONTENT_TYPE, content_type);
+        }
+        let res = app
+            .oneshot(req.body(Body::from(body)).unwrap())
+            .await
+            .unwrap();
+        let status = res.status();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn content_type() -> Option<&'static str> {
+        Some("multipart/form-data; boundary=X-NEXUS-BOUNDARY")
+    }
+
+    #[tokio::test]
+    async fn text_and_file_fields() {
+        let body = form(&[
+            ("title", None, "hello"),
+            ("upload", Some("notes.txt"), "line one\r\nline two"),
+        ]);
+        let (status, fields) =
+            send::<DEFAULT_TOTAL_LIMIT, { u64::MAX }>(content_type(), body).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            fields,
+            "title=hello\nupload notes.txt text/plain=line one\r\nline two"
+        );
+    }
+
+    #[tokio::test]
+    async fn field_limit() {
+        let body = form(&[("small", None, "1234"), ("large", None, "12345")]);
+        let (status, _) = send::<DEFAULT_TOTAL_LIMIT, 4>(content_type(), body.clone()).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let (status, _) = send::<DEFAULT_TOTAL_LIMIT, 5>(content_type(), body).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn total_limit() {
+        let body = form(&[("a", None, "1234"), ("b", None, "5678")]);
+        let len = body.len() as u64;
+        assert!(len > 64);
+
+        let (status, _) = send::<64, { u64::MAX }>(content_type(), body.clone()).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let (status, _) = send::<1024, { u64::MAX }>(content_type(), body).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn invalid_boundary() {
+        let body = || form(&[("title", None, "hello")]);
+        for content_type in [
+            None,
+            Some("multipart/form-data"),
+            Some("application/x-www-form-urlencoded"),
+        ] {
+            let (status, _) = send::<DEFAULT_TOTAL_LIMIT, { u64::MAX }>(content_type, body()).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{:?}", content_type);
+        }
+    }
+
+    #[tokio::test]
+    async fn truncated_body() {
+        let body = form(&[("title", None, "hello")]);
+        let truncated = body[..body.len() - BOUNDARY.len() - 10].to_owned();
+
+        let (status, message) =
+            send::<DEFAULT_TOTAL_LIMIT, { u64::MAX }>(content_type(), truncated).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(message.starts_with("Error parsing `multipart/form-data` request"));
+    }
+
+    #[tokio::test]
+    async fn malformed_part() {
+        // the headers of the part never end
+        let body = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"title\"\r\nhello\r\n--{b}--\r\n",
+            b = BOUNDARY
+        );
+        let (status, _) = send::<DEFAULT_TOTAL_LIMIT, { u64::MAX }>(content_type(), body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
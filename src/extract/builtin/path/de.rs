@@ -0,0 +1,660 @@
+use std::fmt;
+
+use serde::{
+    de::{
+        self, DeserializeSeed, EnumAccess, Error as _, MapAccess, SeqAccess, VariantAccess, Visitor,
+    },
+    forward_to_deserialize_any, Deserializer,
+};
+
+use crate::util::ByteStr;
+
+type Params<'de> = &'de [(ByteStr, ByteStr)];
+
+#[derive(Debug)]
+pub(super) struct PathDeserializerError(String);
+
+impl PathDeserializerError {
+    fn wrong_number_of_params(got: usize, expected: usize) -> Self {
+        Self(format!(
+            "Wrong number of params. Expected {} but got {}",
+            expected, got
+        ))
+    }
+
+    fn parse(key: &str, value: &str, expected_type: &'static str) -> Self {
+        Self(format!(
+            "Cannot parse param `{}` with value `{}` to a `{}`",
+            key, value, expected_type
+        ))
+    }
+
+    fn unsupported_type(name: &'static str) -> Self {
+        Self(format!("Unsupported type `{}`", name))
+    }
+}
+
+impl fmt::Display for PathDeserializerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for PathDeserializerError {}
+
+impl de::Error for PathDeserializerError {
+    fn custom<T>(msg: T) -> Self
+    where
+        T: fmt::Display,
+    {
+        Self(msg.to_string())
+    }
+}
+
+// Deserializes the whole set of url params.
+//
+// Primitives require exactly one param, sequences and tuples read the
+// values in route order and maps/structs are keyed by the param names.
+pub(super) struct PathDeserializer<'de> {
+    url_params: Params<'de>,
+}
+
+impl<'de> PathDeserializer<'de> {
+    pub(super) fn new(url_params: Params<'de>) -> Self {
+        Self { url_params }
+    }
+
+    fn single_value(&self) -> Result<ValueDeserializer<'de>, PathDeserializerError> {
+        match self.url_params {
+            [(key, value)] => Ok(ValueDeserializer { key, value }),
+            _ => Err(PathDeserializerError::wrong_number_of_params(
+                self.url_params.len(),
+                1,
+            )),
+        }
+    }
+}
+
+macro_rules! forward_to_single_value {
+    ($($trait_fn:ident)*) => {
+        $(
+            fn $trait_fn<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+            where
+                V: Visitor<'de>,
+            {
+                self.single_value()?.$trait_fn(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for PathDeserializer<'de> {
+    type Error = PathDeserializerError;
+
+    forward_to_single_value! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_f32 deserialize_f64 deserialize_char
+        deserialize_str deserialize_string deserialize_bytes deserialize_byte_buf
+    }
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if self.url_params.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_seq(SeqDeserializer {
+            params: self.url_params,
+        })
+    }
+
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if self.url_params.len() != len {
+            return Err(PathDeserializerError::wrong_number_of_params(
+                self.url_params.len(),
+                len,
+            ));
+        }
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_map(MapDeserializer {
+            params: self.url_params,
+            value: None,
+        })
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.single_value()?
+            .deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.single_value()?.deserialize_identifier(visitor)
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+}
+
+struct SeqDeserializer<'de> {
+    params: Params<'de>,
+}
+
+impl<'de> SeqAccess<'de> for SeqDeserializer<'de> {
+    type Error = PathDeserializerError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.params.split_first() {
+            Some(((key, value), tail)) => {
+                self.params = tail;
+                seed.deserialize(ValueDeserializer { key, value }).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.params.len())
+    }
+}
+
+struct MapDeserializer<'de> {
+    params: Params<'de>,
+    value: Option<(&'de ByteStr, &'de ByteStr)>,
+}
+
+impl<'de> MapAccess<'de> for MapDeserializer<'de> {
+    type Error = PathDeserializerError;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        match self.params.split_first() {
+            Some(((key, value), tail)) => {
+                self.params = tail;
+                self.value = Some((key, value));
+                seed.deserialize(KeyDeserializer { key }).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        match self.value.take() {
+            Some((key, value)) => seed.deserialize(ValueDeserializer { key, value }),
+            None => Err(PathDeserializerError::custom("value is missing")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.params.len())
+    }
+}
+
+struct KeyDeserializer<'de> {
+    key: &'de ByteStr,
+}
+
+impl<'de> Deserializer<'de> for KeyDeserializer<'de> {
+    type Error = PathDeserializerError;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_borrowed_str(self.key.as_str())
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+macro_rules! parse_value {
+    ($trait_fn:ident, $visit_fn:ident, $ty:literal) => {
+        fn $trait_fn<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+        where
+            V: Visitor<'de>,
+        {
+            let value = self.value.parse().map_err(|_| {
+                PathDeserializerError::parse(self.key.as_str(), self.value.as_str(), $ty)
+            })?;
+            visitor.$visit_fn(value)
+        }
+    };
+}
+
+// Deserializes a single param value, naming the param in parse errors.
+struct ValueDeserializer<'de> {
+    key: &'de ByteStr,
+    value: &'de ByteStr,
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = PathDeserializerError;
+
+    parse_value!(deserialize_bool, visit_bool, "bool");
+    parse_value!(deserialize_i8, visit_i8, "i8");
+    parse_value!(deserialize_i16, visit_i16, "i16");
+    parse_value!(deserialize_i32, visit_i32, "i32");
+    parse_value!(deserialize_i64, visit_i64, "i64");
+    parse_value!(deserialize_u8, visit_u8, "u8");
+    parse_value!(deserialize_u16, visit_u16, "u16");
+    parse_value!(deserialize_u32, visit_u32, "u32");
+    parse_value!(deserialize_u64, visit_u64, "u64");
+    parse_value!(deserialize_f32, visit_f32, "f32");
+    parse_value!(deserialize_f64, visit_f64, "f64");
+    parse_value!(deserialize_char, visit_char, "char");
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_borrowed_str(self.value.as_str())
+    }
+
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_borrowed_bytes(self.value.as_bytes())
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(PathDeserializerError::unsupported_type("seq"))
+    }
+
+    fn deserialize_tuple<V>(self, _len: usize, _visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(PathDeserializerError::unsupported_type("tuple"))
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        name: &'static str,
+        _len: usize,
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(PathDeserializerError::unsupported_type(name))
+    }
+
+    fn deserialize_map<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(PathDeserializerError::unsupported_type("map"))
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        name: &'static str,
+        _fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(PathDeserializerError::unsupported_type(name))
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_enum(EnumDeserializer { value: self.value })
+    }
+
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+}
+
+// Only unit variants can be expressed by a single path segment.
+struct EnumDeserializer<'de> {
+    value: &'de ByteStr,
+}
+
+impl<'de> EnumAccess<'de> for EnumDeserializer<'de> {
+    type Error = PathDeserializerError;
+    type Variant = UnitVariant;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let variant = seed.deserialize(KeyDeserializer { key: self.value })?;
+        Ok((variant, UnitVariant))
+    }
+}
+
+struct UnitVariant;
+
+impl<'de> VariantAccess<'de> for UnitVariant {
+    type Error = PathDeserializerError;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T>(self, _seed: T) -> Result<T::Value, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        Err(PathDeserializerError::unsupported_type(
+            "newtype enum variant",
+        ))
+    }
+
+    fn tuple_variant<V>(self, _len: usize, _visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(PathDeserializerError::unsupported_type(
+            "tuple enum variant",
+        ))
+    }
+
+    fn struct_variant<V>(
+        self,
+        _fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(PathDeserializerError::unsupported_type(
+            "struct enum variant",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    fn params(params: &[(&str, &str)]) -> Vec<(ByteStr, ByteStr)> {
+        params
+            .iter()
+            .map(|(key, value)| (ByteStr::new(key), ByteStr::new(value)))
+            .collect()
+    }
+
+    fn deserialize<'de, T>(params: &'de [(ByteStr, ByteStr)]) -> Result<T, String>
+    where
+        T: serde::Deserialize<'de>,
+    {
+        T::deserialize(PathDeserializer::new(params)).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn single_value() {
+        let url_params = params(&[("id", "42")]);
+        assert_eq!(deserialize::<u32>(&url_params), Ok(42));
+        assert_eq!(deserialize::<String>(&url_params), Ok("42".to_owned()));
+        assert_eq!(deserialize::<&str>(&url_params), Ok("42"));
+    }
+
+    #[test]
+    fn tuple() {
+        let url_params = params(&[("user", "nexus"), ("id", "7")]);
+        assert_eq!(
+            deserialize::<(String, u32)>(&url_params),
+            Ok(("nexus".to_owned(), 7))
+        );
+    }
+
+    #[test]
+    fn structs() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Params {
+            id: u32,
+            name: String,
+        }
+
+        // fields are matched by name, not position
+        let url_params = params(&[("name", "nexus"), ("id", "7")]);
+        assert_eq!(
+            deserialize::<Params>(&url_params),
+            Ok(Params {
+                id: 7,
+                name: "nexus".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn map() {
+        let url_params = params(&[("a", "1"), ("b", "2")]);
+        let map = deserialize::<HashMap<String, u32>>(&url_params).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["a"], 1);
+        assert_eq!(map["b"], 2);
+    }
+
+    #[test]
+    fn unit_enum() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        #[serde(rename_all = "lowercase")]
+        enum Version {
+            V1,
+            V2,
+        }
+
+        let url_params = params(&[("version", "v2")]);
+        assert_eq!(deserialize::<Version>(&url_params), Ok(Version::V2));
+    }
+
+    #[test]
+    fn wrong_number_of_params() {
+        let url_params = params(&[("a", "1"), ("b", "2")]);
+        assert_eq!(
+            deserialize::<u32>(&url_params),
+            Err("Wrong number of params. Expected 1 but got 2".to_owned())
+        );
+        assert_eq!(
+            deserialize::<(u32, u32, u32)>(&url_params),
+            Err("Wrong number of params. Expected 3 but got 2".to_owned())
+        );
+    }
+
+    #[test]
+    fn parse_error() {
+        let url_params = params(&[("id", "x")]);
+        assert_eq!(
+            deserialize::<u32>(&url_params),
+            Err("Cannot parse param `id` with value `x` to a `u32`".to_owned())
+        );
+
+        let url_params = params(&[("user", "nexus"), ("id", "x")]);
+        assert_eq!(
+            deserialize::<(String, u32)>(&url_params),
+            Err("Cannot parse param `id` with value `x` to a `u32`".to_owned())
+        );
+    }
+}
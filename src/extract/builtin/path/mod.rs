@@ -0,0 +1,132 @@
+use std::ops::Deref;
+
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+
+use self::de::PathDeserializer;
+use crate::{
+    extract::{
+        rejection::{
+            InvalidPathParam, InvalidUtf8InPathParam, MissingRouteParams, PathParamsRejection,
+        },
+        FromRequest, RequestParts,
+    },
+    router::route::UrlParams,
+};
+
+mod de;
+
+// Extractor for the params captured by `:name` segments of the route.
+//
+// `T` can be a single value (when the route has exactly one param), a tuple
+// with one element per param in route order, or a struct/map keyed by the
+// param names.
+//
+// Values are percent-decoded, a value that isn't valid UTF-8 once decoded
+// rejects the request.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Path<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for Path<T>
+where
+    T: DeserializeOwned + Send,
+    B: Send,
+{
+    type Rejection = PathParamsRejection;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let url_params = match req
+            .extensions()
+            .and_then(|ext| ext.get::<Option<UrlParams>>())
+        {
+            Some(Some(UrlParams::Params(params))) => params.as_slice(),
+            Some(Some(UrlParams::InvalidUtf8InPathParam { key })) => {
+                return Err(InvalidUtf8InPathParam::new(key.as_str()).into())
+            }
+            Some(None) => &[],
+            None => return Err(MissingRouteParams.into()),
+        };
+
+        T::deserialize(PathDeserializer::new(url_params))
+            .map(Path)
+            .map_err(|err| InvalidPathParam::new(err.to_string()).into())
+    }
+}
+
+impl<T> Deref for Path<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+
+    use http::{Request, Response, StatusCode};
+    use hyper::Body;
+    use tower::{Service, ServiceExt};
+
+    use super::*;
+    use crate::{handler::get, Router};
+
+    async fn call<S, B>(app: S, uri: &str) -> (StatusCode, String)
+    where
+        S: Service<Request<Body>, Response = Response<B>>,
+        S::Error: fmt::Debug,
+        B: http_body::Body,
+        B::Error: fmt::Debug,
+    {
+        let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn percent_decodes_params() {
+        let app = Router::new().route("/e/:name", get(|Path(name): Path<String>| async { name }));
+
+        assert_eq!(
+            call(app.clone(), "/e/john%20doe").await,
+            (StatusCode::OK, "john doe".to_owned())
+        );
+        assert_eq!(
+            call(app, "/e/caf%C3%A9").await,
+            (StatusCode::OK, "café".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn percent_decodes_params_of_nested_routes() {
+        let api = Router::new().route(
+            "/users/:name",
+            get(|Path((version, name)): Path<(String, String)>| async move {
+                format!("{} {}", version, name)
+            }),
+        );
+        let app = Router::new().nest("/api/:version", api);
+
+        assert_eq!(
+            call(app, "/api/v%201/users/a%2Fb").await,
+            (StatusCode::OK, "v 1 a/b".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_utf8() {
+        let app = Router::new().route("/e/:name", get(|Path(name): Path<String>| async { name }));
+
+        assert_eq!(
+            call(app, "/e/%FF").await,
+            (
+                StatusCode::BAD_REQUEST,
+                "Invalid UTF-8 in `name`".to_owned()
+            )
+        );
+    }
+}
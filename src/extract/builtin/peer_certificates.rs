@@ -0,0 +1,45 @@
+use std::ops::Deref;
+
+use async_trait::async_trait;
+use rustls::Certificate;
+
+use crate::extract::{
+    rejection::{ExtensionAlreadyExtracted, MissingPeerCertificates, PeerCertificatesRejection},
+    FromRequest, RequestParts,
+};
+
+// Extractor for the certificate chain the client presented during the TLS
+// handshake, leaf certificate first.
+//
+// The chain is empty when the client didn't send a certificate, so handlers
+// behind a config with optional client auth can decide for themselves.
+// Requests not served with `Serve::tls` are rejected with a `500`.
+#[derive(Debug, Clone, Default)]
+pub struct PeerCertificates(pub Vec<Certificate>);
+
+#[async_trait]
+impl<B> FromRequest<B> for PeerCertificates
+where
+    B: Send,
+{
+    type Rejection = PeerCertificatesRejection;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let certs = req
+            .extensions()
+            .ok_or(ExtensionAlreadyExtracted)?
+            .get::<Self>()
+            .cloned()
+            .ok_or(MissingPeerCertificates)?;
+
+        Ok(certs)
+    }
+}
+
+impl Deref for PeerCertificates {
+    type Target = [Certificate];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
@@ -0,0 +1,533 @@
+// WebSocket support.
+//
+// `WebSocketUpgrade` checks the handshake headers of the request, and
+// `on_upgrade` answers with `101 Switching Protocols` and runs the callback
+// on a new task once hyper has handed over the connection. The callback
+// gets a `WebSocket`, a `Stream` and `Sink` of `Message`s.
+
+use std::{
+    borrow::Cow,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use async_trait::async_trait;
+use futures_util::{
+    sink::{Sink, SinkExt},
+    stream::{Stream, StreamExt},
+};
+use http::{header, HeaderMap, HeaderValue, Method, Response, StatusCode};
+use hyper::upgrade::{OnUpgrade, Upgraded};
+use tokio_tungstenite::{
+    tungstenite::{
+        self as ts,
+        handshake::derive_accept_key,
+        protocol::{frame::coding::CloseCode, Role, WebSocketConfig},
+    },
+    WebSocketStream,
+};
+
+use crate::{
+    body::{self, BoxBody},
+    extract::{
+        rejection::{
+            ConnectionNotUpgradable, ExtensionAlreadyExtracted, HeadersAlreadyExtracted,
+            InvalidConnectionHeader, InvalidUpgradeHeader, InvalidWebSocketVersionHeader,
+            MethodNotGet, WebSocketKeyHeaderMissing, WebSocketUpgradeRejection,
+        },
+        FromRequest, RequestParts,
+    },
+    Error,
+};
+
+// Extractor for the opening handshake of a WebSocket connection.
+pub struct WebSocketUpgrade {
+    config: WebSocketConfig,
+    sec_websocket_key: HeaderValue,
+    // protocols requested by the client, in order of preference
+    sec_websocket_protocol: Option<HeaderValue>,
+    // protocol selected with `protocols`
+    protocol: Option<HeaderValue>,
+    on_upgrade: OnUpgrade,
+}
+
+impl std::fmt::Debug for WebSocketUpgrade {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebSocketUpgrade")
+            .field("config", &self.config)
+            .field("sec_websocket_key", &self.sec_websocket_key)
+            .field("sec_websocket_protocol", &self.sec_websocket_protocol)
+            .field("protocol", &self.protocol)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for WebSocketUpgrade
+where
+    B: Send,
+{
+    type Rejection = WebSocketUpgradeRejection;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        if req.method() != Method::GET {
+            return Err(MethodNotGet.into());
+        }
+
+        let headers = req.headers().ok_or(HeadersAlreadyExtracted)?;
+
+        if !header_contains(headers, header::CONNECTION, "upgrade") {
+            return Err(InvalidConnectionHeader.into());
+        }
+
+        if !header_eq(headers, header::UPGRADE, "websocket") {
+            return Err(InvalidUpgradeHeader.into());
+        }
+
+        if !header_eq(headers, header::SEC_WEBSOCKET_VERSION, "13") {
+            return Err(InvalidWebSocketVersionHeader.into());
+        }
+
+        let sec_websocket_key = headers
+            .get(header::SEC_WEBSOCKET_KEY)
+            .ok_or(WebSocketKeyHeaderMissing)?
+            .clone();
+
+        let sec_websocket_protocol = headers.get(header::SEC_WEBSOCKET_PROTOCOL).cloned();
+
+        // only present for HTTP/1.1 connections
+        let on_upgrade = req
+            .extensions_mut()
+            .ok_or(ExtensionAlreadyExtracted)?
+            .remove::<OnUpgrade>()
+            .ok_or(ConnectionNotUpgradable)?;
+
+        Ok(Self {
+            config: Default::default(),
+            sec_websocket_key,
+            sec_websocket_protocol,
+            protocol: None,
+            on_upgrade,
+        })
+    }
+}
+
+impl WebSocketUpgrade {
+    // largest message accepted from the client, 64 MiB by default
+    pub fn max_message_size(mut self, max: usize) -> Self {
+        self.config.max_message_size = Some(max);
+        self
+    }
+
+    // largest frame accepted from the client, 16 MiB by default
+    pub fn max_frame_size(mut self, max: usize) -> Self {
+        self.config.max_frame_size = Some(max);
+        self
+    }
+
+    // Protocols supported by the server. The first protocol requested by the
+    // client that is in `protocols` is selected and sent back in the
+    // response, if none matches the handshake succeeds without a protocol.
+    pub fn protocols<I>(mut self, protocols: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<Cow<'static, str>>,
+    {
+        let supported = protocols.into_iter().map(Into::into).collect::<Vec<_>>();
+
+        self.protocol = self
+            .sec_websocket_protocol
+            .as_ref()
+            .and_then(|requested| requested.to_str().ok())
+            .and_then(|requested| {
+                requested
+                    .split(',')
+                    .map(str::trim)
+                    .find(|requested| supported.iter().any(|p| p == requested))
+            })
+            .and_then(|protocol| HeaderValue::from_str(protocol).ok());
+
+        self
+    }
+
+    // Finish the handshake and call `callback` with the connection.
+    pub fn on_upgrade<F, Fut>(self, callback: F) -> Response<BoxBody>
+    where
+        F: FnOnce(WebSocket) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let Self {
+            config,
+            sec_websocket_key,
+            protocol,
+            on_upgrade,
+            ..
+        } = self;
+
+        let mut res = Response::new(body::empty());
+        *res.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+        let headers = res.headers_mut();
+        headers.insert(header::CONNECTION, HeaderValue::from_static("upgrade"));
+        headers.insert(header::UPGRADE, HeaderValue::from_static("websocket"));
+        headers.insert(
+            header::SEC_WEBSOCKET_ACCEPT,
+            derive_accept_key(sec_websocket_key.as_bytes())
+                .parse()
+                .expect("accept key is valid base64"),
+        );
+        if let Some(protocol) = protocol.clone() {
+            headers.insert(header::SEC_WEBSOCKET_PROTOCOL, protocol);
+        }
+
+        tokio::spawn(async move {
+            let upgraded = match on_upgrade.await {
+                Ok(upgraded) => upgraded,
+                Err(err) => {
+                    tracing::debug!(%err, "WebSocket upgrade failed");
+                    return;
+                }
+            };
+            let inner =
+                WebSocketStream::from_raw_socket(upgraded, Role::Server, Some(config)).await;
+
+            callback(WebSocket { inner, protocol }).await;
+        });
+
+        res
+    }
+}
+
+fn header_eq(headers: &HeaderMap, key: header::HeaderName, value: &'static str) -> bool {
+    headers
+        .get(&key)
+        .is_some_and(|header| header.as_bytes().eq_ignore_ascii_case(value.as_bytes()))
+}
+
+fn header_contains(headers: &HeaderMap, key: header::HeaderName, value: &'static str) -> bool {
+    headers
+        .get(&key)
+        .and_then(|header| header.to_str().ok())
+        .is_some_and(|header| {
+            header
+                .split(',')
+                .any(|token| token.trim().eq_ignore_ascii_case(value))
+        })
+}
+
+// An upgraded WebSocket connection.
+#[derive(Debug)]
+pub struct WebSocket {
+    inner: WebSocketStream<Upgraded>,
+    protocol: Option<HeaderValue>,
+}
+
+impl WebSocket {
+    // `None` once the connection is closed
+    pub async fn recv(&mut self) -> Option<Result<Message, Error>> {
+        self.next().await
+    }
+
+    pub async fn send(&mut self, msg: Message) -> Result<(), Error> {
+        SinkExt::send(self, msg).await
+    }
+
+    // send a close frame and wait for the connection to close
+    pub async fn close(mut self) -> Result<(), Error> {
+        SinkExt::close(&mut self).await
+    }
+
+    // protocol selected with `WebSocketUpgrade::protocols`
+    pub fn protocol(&self) -> Option<&HeaderValue> {
+        self.protocol.as_ref()
+    }
+}
+
+impl Stream for WebSocket {
+    type Item = Result<Message, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let msg = match futures_util::ready!(self.inner.poll_next_unpin(cx)) {
+                Some(Ok(msg)) => msg,
+                Some(Err(err)) => return Poll::Ready(Some(Err(Error::new(err)))),
+                None => return Poll::Ready(None),
+            };
+
+            // raw frames are never returned when reading
+            if let Some(msg) = Message::from_tungstenite(msg) {
+                return Poll::Ready(Some(Ok(msg)));
+            }
+        }
+    }
+}
+
+impl Sink<Message> for WebSocket {
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_ready(cx).map_err(Error::new)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), Self::Error> {
+        Pin::new(&mut self.inner)
+            .start_send(item.into_tungstenite())
+            .map_err(Error::new)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx).map_err(Error::new)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx).map_err(Error::new)
+    }
+}
+
+// A WebSocket message.
+//
+// Pings are answered with pongs automatically, they are only returned so
+// the handler can see them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    // `None` when the peer closed without a code
+    Close(Option<CloseFrame>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: Cow<'static, str>,
+}
+
+impl Message {
+    pub fn into_data(self) -> Vec<u8> {
+        match self {
+            Self::Text(text) => text.into_bytes(),
+            Self::Binary(data) | Self::Ping(data) | Self::Pong(data) => data,
+            Self::Close(frame) => frame
+                .map(|frame| frame.reason.into_owned().into_bytes())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn into_tungstenite(self) -> ts::Message {
+        match self {
+            Self::Text(text) => ts::Message::Text(text),
+            Self::Binary(data) => ts::Message::Binary(data),
+            Self::Ping(data) => ts::Message::Ping(data),
+            Self::Pong(data) => ts::Message::Pong(data),
+            Self::Close(frame) => ts::Message::Close(frame.map(|frame| ts::protocol::CloseFrame {
+                code: CloseCode::from(frame.code),
+                reason: frame.reason,
+            })),
+        }
+    }
+
+    fn from_tungstenite(msg: ts::Message) -> Option<Self> {
+        let msg = match msg {
+            ts::Message::Text(text) => Self::Text(text),
+            ts::Message::Binary(data) => Self::Binary(data),
+            ts::Message::Ping(data) => Self::Ping(data),
+            ts::Message::Pong(data) => Self::Pong(data),
+            ts::Message::Close(frame) => Self::Close(frame.map(|frame| CloseFrame {
+                code: frame.code.into(),
+                reason: Cow::Owned(frame.reason.into_owned()),
+            })),
+            ts::Message::Frame(_) => return None,
+        };
+        Some(msg)
+    }
+}
+
+impl From<String> for Message {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
+impl From<&str> for Message {
+    fn from(text: &str) -> Self {
+        Self::Text(text.to_owned())
+    }
+}
+
+impl From<Vec<u8>> for Message {
+    fn from(data: Vec<u8>) -> Self {
+        Self::Binary(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::IntoFuture;
+
+    use http::Request;
+    use hyper::Body;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+    use crate::{handler::get, response::IntoResponse, serve, Router};
+
+    // the handshake from RFC 6455 section 1.3
+    const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+    const ACCEPT: &str = "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=";
+
+    fn handshake() -> http::request::Builder {
+        Request::builder()
+            .uri("/ws")
+            .header(header::CONNECTION, "keep-alive, Upgrade")
+            .header(header::UPGRADE, "websocket")
+            .header(header::SEC_WEBSOCKET_VERSION, "13")
+            .header(header::SEC_WEBSOCKET_KEY, KEY)
+    }
+
+    async fn reject(req: http::request::Builder) -> WebSocketUpgradeRejection {
+        let mut req = RequestParts::new(req.body(Body::empty()).unwrap());
+        WebSocketUpgrade::from_request(&mut req).await.unwrap_err()
+    }
+
+    fn without(name: header::HeaderName) -> http::request::Builder {
+        let mut req = handshake();
+        req.headers_mut().unwrap().remove(name);
+        req
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_handshakes() {
+        let rejection = reject(handshake().method(Method::POST)).await;
+        assert!(matches!(
+            rejection,
+            WebSocketUpgradeRejection::MethodNotGet(_)
+        ));
+        assert_eq!(
+            rejection.into_response().status(),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+
+        let rejection = reject(without(header::CONNECTION)).await;
+        assert!(matches!(
+            rejection,
+            WebSocketUpgradeRejection::InvalidConnectionHeader(_)
+        ));
+        assert_eq!(rejection.into_response().status(), StatusCode::BAD_REQUEST);
+
+        let rejection = reject(without(header::UPGRADE)).await;
+        assert!(matches!(
+            rejection,
+            WebSocketUpgradeRejection::InvalidUpgradeHeader(_)
+        ));
+        assert_eq!(rejection.into_response().status(), StatusCode::BAD_REQUEST);
+
+        let rejection = reject(without(header::SEC_WEBSOCKET_VERSION)).await;
+        assert!(matches!(
+            rejection,
+            WebSocketUpgradeRejection::InvalidWebSocketVersionHeader(_)
+        ));
+
+        let rejection = reject(without(header::SEC_WEBSOCKET_KEY)).await;
+        assert!(matches!(
+            rejection,
+            WebSocketUpgradeRejection::WebSocketKeyHeaderMissing(_)
+        ));
+        assert_eq!(rejection.into_response().status(), StatusCode::BAD_REQUEST);
+
+        // a valid handshake on a connection hyper can't upgrade
+        let rejection = reject(handshake()).await;
+        assert!(matches!(
+            rejection,
+            WebSocketUpgradeRejection::ConnectionNotUpgradable(_)
+        ));
+        assert_eq!(
+            rejection.into_response().status(),
+            StatusCode::UPGRADE_REQUIRED
+        );
+    }
+
+    // echoes every text and binary message back
+    async fn spawn_echo_server() -> std::net::SocketAddr {
+        let app = Router::new().route(
+            "/ws",
+            get(|ws: WebSocketUpgrade| async move {
+                ws.protocols(["echo"]).on_upgrade(|mut socket| async move {
+                    while let Some(Ok(msg)) = socket.recv().await {
+                        if let Message::Text(_) | Message::Binary(_) = msg {
+                            if socket.send(msg).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                })
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, app.into_make_service()).into_future());
+        addr
+    }
+
+    #[tokio::test]
+    async fn accept_key() {
+        let addr = spawn_echo_server().await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let req = format!(
+            "GET /ws HTTP/1.1\r\nHost: {}\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\
+             Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: {}\r\n\
+             Sec-WebSocket-Protocol: chat, echo\r\n\r\n",
+            addr, KEY
+        );
+        stream.write_all(req.as_bytes()).await.unwrap();
+
+        let mut buf = vec![0; 1024];
+        let n = stream.read(&mut buf).await.unwrap();
+        let res = String::from_utf8_lossy(&buf[..n]).to_ascii_lowercase();
+        assert!(
+            res.starts_with("http/1.1 101 switching protocols\r\n"),
+            "{}",
+            res
+        );
+        assert!(
+            res.contains(&format!(
+                "sec-websocket-accept: {}\r\n",
+                ACCEPT.to_ascii_lowercase()
+            )),
+            "{}",
+            res
+        );
+        assert!(res.contains("sec-websocket-protocol: echo\r\n"), "{}", res);
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let addr = spawn_echo_server().await;
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut client, res) =
+            tokio_tungstenite::client_async(format!("ws://{}/ws", addr), stream)
+                .await
+                .unwrap();
+        assert_eq!(res.status(), StatusCode::SWITCHING_PROTOCOLS);
+
+        client
+            .send(ts::Message::Text("hello".to_owned()))
+            .await
+            .unwrap();
+        let reply = client.next().await.unwrap().unwrap();
+        assert_eq!(reply, ts::Message::Text("hello".to_owned()));
+
+        client
+            .send(ts::Message::Binary(vec![1, 2, 3]))
+            .await
+            .unwrap();
+        let reply = client.next().await.unwrap().unwrap();
+        assert_eq!(reply, ts::Message::Binary(vec![1, 2, 3]));
+
+        client.close(None).await.unwrap();
+    }
+}
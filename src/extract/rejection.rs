@@ -26,7 +26,7 @@ define_rejection! {
 
 define_rejection! {
      #[status = BAD_REQUEST]
-     #[body="Filed to parse the request body as JSON"]
+     #[body="Failed to parse the request body as JSON"]
 
      pub struct InvalidJsonBody(Error);
 }
@@ -45,6 +45,84 @@ define_rejection! {
      pub struct MissingExtension(Error);
 }
 
+define_rejection! {
+     #[status = INTERNAL_SERVER_ERROR]
+     #[body = "Missing connection info, serve the app with `into_make_service_with_connect_info`"]
+
+     pub struct MissingConnectInfo;
+}
+
+#[cfg(feature = "tls")]
+define_rejection! {
+     #[status = INTERNAL_SERVER_ERROR]
+     #[body = "Missing peer certificates, serve the app over TLS"]
+
+     pub struct MissingPeerCertificates;
+}
+
+#[cfg(feature = "ws")]
+define_rejection! {
+     #[status = METHOD_NOT_ALLOWED]
+     #[body = "Request method must be `GET`"]
+
+     pub struct MethodNotGet;
+}
+
+#[cfg(feature = "ws")]
+define_rejection! {
+     #[status = BAD_REQUEST]
+     #[body = "Connection header did not include `upgrade`"]
+
+     pub struct InvalidConnectionHeader;
+}
+
+#[cfg(feature = "ws")]
+define_rejection! {
+     #[status = BAD_REQUEST]
+     #[body = "`Upgrade` header did not include `websocket`"]
+
+     pub struct InvalidUpgradeHeader;
+}
+
+#[cfg(feature = "ws")]
+define_rejection! {
+     #[status = BAD_REQUEST]
+     #[body = "`Sec-WebSocket-Version` header did not include `13`"]
+
+     pub struct InvalidWebSocketVersionHeader;
+}
+
+#[cfg(feature = "ws")]
+define_rejection! {
+     #[status = BAD_REQUEST]
+     #[body = "`Sec-WebSocket-Key` header missing"]
+
+     pub struct WebSocketKeyHeaderMissing;
+}
+
+#[cfg(feature = "ws")]
+define_rejection! {
+     #[status = UPGRADE_REQUIRED]
+     #[body = "WebSocket request couldn't be upgraded, the connection doesn't support upgrades"]
+
+     pub struct ConnectionNotUpgradable;
+}
+
+#[cfg(feature = "multipart")]
+define_rejection! {
+     #[status = BAD_REQUEST]
+     #[body = "Invalid `boundary` for `multipart/form-data` request"]
+
+     pub struct InvalidBoundary;
+}
+
+define_rejection! {
+     #[status = INTERNAL_SERVER_ERROR]
+     #[body = "No matched path found, the request wasn't routed by a `Router`"]
+
+     pub struct MatchedPathMissing;
+}
+
 define_rejection! {
      #[status = BAD_REQUEST]
      #[body = "Failed to buffer the request body"]
@@ -68,13 +146,6 @@ define_rejection! {
 
 }
 
-define_rejection! {
-     #[status = LENGTH_REQUIRED]
-     #[body = "Content length header is required"]
-
-     pub struct LengthRequired;
-}
-
 define_rejection! {
      #[status = INTERNAL_SERVER_ERROR]
      #[body = "No url params found or matched the route. This is a bug in nexus,please open the issue"]
@@ -91,7 +162,7 @@ define_rejection! {
 
 define_rejection! {
      #[status = BAD_REQUEST]
-     #[body = "From requests must have `Content-Type:x-www-from-urlencoded`"]
+     #[body = "Form requests must have `Content-Type:application/x-www-form-urlencoded`"]
 
      pub struct InvalidFormContentType;
 }
@@ -100,7 +171,7 @@ define_rejection! {
 pub struct InvalidPathParam(String);
 
 impl InvalidPathParam {
-    pub(super) fn new(err: impl Into<String>) -> Self {
+    pub(crate) fn new(err: impl Into<String>) -> Self {
         InvalidPathParam(err.into())
     }
 }
@@ -124,6 +195,37 @@ impl std::fmt::Display for InvalidPathParam {
 
 impl std::error::Error for InvalidPathParam {}
 
+// A path param wasn't valid UTF-8 once percent-decoded.
+#[derive(Debug)]
+pub struct InvalidUtf8InPathParam {
+    key: String,
+}
+
+impl InvalidUtf8InPathParam {
+    pub(crate) fn new(key: impl Into<String>) -> Self {
+        Self { key: key.into() }
+    }
+}
+
+impl IntoResponse for InvalidUtf8InPathParam {
+    type Body = Full<Bytes>;
+    type BodyError = Infallible;
+
+    fn into_response(self) -> http::Response<Self::Body> {
+        let mut res = http::Response::new(Full::from(self.to_string()));
+        *res.status_mut() = http::StatusCode::BAD_REQUEST;
+        res
+    }
+}
+
+impl std::fmt::Display for InvalidUtf8InPathParam {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "Invalid UTF-8 in `{}`", self.key)
+    }
+}
+
+impl std::error::Error for InvalidUtf8InPathParam {}
+
 #[derive(Debug)]
 pub struct FailedToDeserializeQueryString {
     error: Error,
@@ -172,7 +274,7 @@ composite_rejection! {
 }
 
 composite_rejection! {
-     pub enum FromRejection {
+     pub enum FormRejection {
           InvalidFormContentType,
           FailedToDeserializeQueryString,
           FailedToBufferBody,
@@ -183,8 +285,11 @@ composite_rejection! {
 
 composite_rejection! {
      pub enum JsonRejection {
-          MissingExtension,
-          ExtensionAlreadyExtracted
+          InvalidJsonBody,
+          MissingJsonContentType,
+          FailedToBufferBody,
+          BodyAlreadyExtracted,
+          HeadersAlreadyExtracted
      }
 }
 
@@ -195,9 +300,55 @@ composite_rejection! {
     }
 }
 
+composite_rejection! {
+    pub enum ConnectInfoRejection {
+         MissingConnectInfo,
+         ExtensionAlreadyExtracted
+    }
+}
+
+#[cfg(feature = "tls")]
+composite_rejection! {
+    pub enum PeerCertificatesRejection {
+         MissingPeerCertificates,
+         ExtensionAlreadyExtracted
+    }
+}
+
+#[cfg(feature = "ws")]
+composite_rejection! {
+    pub enum WebSocketUpgradeRejection {
+         MethodNotGet,
+         InvalidConnectionHeader,
+         InvalidUpgradeHeader,
+         InvalidWebSocketVersionHeader,
+         WebSocketKeyHeaderMissing,
+         ConnectionNotUpgradable,
+         HeadersAlreadyExtracted,
+         ExtensionAlreadyExtracted
+    }
+}
+
+#[cfg(feature = "multipart")]
+composite_rejection! {
+    pub enum MultipartRejection {
+         InvalidBoundary,
+         BodyAlreadyExtracted,
+         HeadersAlreadyExtracted
+    }
+}
+
+composite_rejection! {
+    pub enum MatchedPathRejection {
+         MatchedPathMissing,
+         ExtensionAlreadyExtracted
+    }
+}
+
 composite_rejection! {
      pub enum  PathParamsRejection {
           InvalidPathParam,
+          InvalidUtf8InPathParam,
           MissingRouteParams,
      }
 }
@@ -227,16 +378,15 @@ composite_rejection! {
 
 #[derive(Debug)]
 #[non_exhaustive]
-
 pub enum ContentLengthLimitRejection<T> {
     #[allow(missing_docs)]
     PayloadTooLarge(PayloadTooLarge),
 
     #[allow(missing_docs)]
-    LengthRequired(LengthRequired),
+    HeadersAlreadyExtracted(HeadersAlreadyExtracted),
 
     #[allow(missing_docs)]
-    HeadersAlreadyExtracted(HeadersAlreadyExtracted),
+    FailedToBufferBody(FailedToBufferBody),
 
     #[allow(missing_docs)]
     Inner(T),
@@ -252,8 +402,8 @@ where
     fn into_response(self) -> http::Response<Self::Body> {
         match self {
             Self::PayloadTooLarge(inner) => inner.into_response().map(box_body),
-            Self::LengthRequired(inner) => inner.into_response().map(box_body),
             Self::HeadersAlreadyExtracted(inner) => inner.into_response().map(box_body),
+            Self::FailedToBufferBody(inner) => inner.into_response().map(box_body),
             Self::Inner(inner) => inner.into_response().map(box_body),
         }
     }
@@ -266,8 +416,8 @@ where
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::PayloadTooLarge(inner) => inner.fmt(f),
-            Self::LengthRequired(inner) => inner.fmt(f),
             Self::HeadersAlreadyExtracted(inner) => inner.fmt(f),
+            Self::FailedToBufferBody(inner) => inner.fmt(f),
             Self::Inner(inner) => inner.fmt(f),
         }
     }
@@ -280,8 +430,8 @@ where
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             Self::PayloadTooLarge(inner) => Some(inner),
-            Self::LengthRequired(inner) => Some(inner),
             Self::HeadersAlreadyExtracted(inner) => Some(inner),
+            Self::FailedToBufferBody(inner) => Some(inner),
             Self::Inner(inner) => Some(inner),
         }
     }
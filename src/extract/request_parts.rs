@@ -1,6 +1,7 @@
-use std::{convert::Infallible, pin::Pin};
+use std::{convert::Infallible, pin::Pin, sync::Arc};
 
 use async_trait::async_trait;
+use bytes::Bytes;
 use futures_util::stream::Stream;
 use http::{Method, Request, Uri};
 
@@ -84,6 +85,62 @@ where
     }
 }
 
+// The full uri of the request, before any nested router stripped its
+// prefix. Outside of nested routers it is the same as `Uri`.
+#[derive(Debug, Clone)]
+pub struct OriginalUri(pub Uri);
+
+#[async_trait]
+impl<B> FromRequest<B> for OriginalUri
+where
+    B: Send,
+{
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let uri = req
+            .extensions()
+            .and_then(|ext| ext.get::<OriginalUri>())
+            .map(|original_uri| original_uri.0.clone())
+            .unwrap_or_else(|| req.uri().clone());
+
+        Ok(OriginalUri(uri))
+    }
+}
+
+// The route pattern that matched the request, e.g. `/users/:id`. Routes of
+// nested routers include the path they are nested at.
+//
+// The router also adds it to the extensions of the response, so middleware
+// around the router can see which route handled a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchedPath(pub(crate) Arc<str>);
+
+impl MatchedPath {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for MatchedPath
+where
+    B: Send,
+{
+    type Rejection = MatchedPathRejection;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let matched_path = req
+            .extensions()
+            .ok_or(ExtensionAlreadyExtracted)?
+            .get::<Self>()
+            .cloned()
+            .ok_or(MatchedPathMissing)?;
+
+        Ok(matched_path)
+    }
+}
+
 #[derive(Debug)]
 pub struct BodyStream<B = crate::body::Body>(B);
 
@@ -115,6 +172,26 @@ where
     }
 }
 
+#[async_trait]
+impl<B> FromRequest<B> for Bytes
+where
+    B: http_body::Body + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = BytesRejection;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let body = take_body(req)?;
+
+        let bytes = hyper::body::to_bytes(body)
+            .await
+            .map_err(FailedToBufferBody::from_err)?;
+
+        Ok(bytes)
+    }
+}
+
 #[async_trait]
 impl<B> FromRequest<B> for String
 where
@@ -7,17 +7,12 @@ use std::{
 };
 
 use bytes::Bytes;
-use headers::UserAgent;
-use http::header::USER_AGENT;
 use tower::ServiceExt;
 
-use self::into_service::IntoService;
+pub use self::into_service::IntoService;
 use crate::{
     body::{box_body, BoxBody},
-    extract::{
-        builtin::{query::Query, typed_header::TypedHeader},
-        FromRequest,
-    },
+    extract::FromRequest,
     response::IntoResponse,
     service::HandleError,
     util::Either,
@@ -40,10 +35,16 @@ use async_trait::async_trait;
 use http::{Request, Response};
 use tower_service::Service;
 
-use crate::router::{empty_router::EmptyRouter, method_filter::MethodFilter};
+use crate::router::{
+    empty_router::EmptyRouter,
+    method_filter::{AllowedMethods, MethodFilter},
+};
 
 pub struct OnMethod<H, B, T, F> {
     pub(crate) method: MethodFilter,
+    // `method` and the methods of every `OnMethod` in `fallback`, so the
+    // router can tell which routes on the same path overlap
+    pub(crate) all_methods: MethodFilter,
     pub(crate) handler: H,
     pub(crate) fallback: F,
     pub(crate) _marker: PhantomData<fn() -> (B, T)>,
@@ -57,6 +58,7 @@ where
     fn clone(&self) -> Self {
         Self {
             method: self.method,
+            all_methods: self.all_methods,
             handler: self.handler.clone(),
             fallback: self.fallback.clone(),
             _marker: PhantomData,
@@ -75,6 +77,7 @@ where
 {
     OnMethod {
         method,
+        all_methods: method,
         handler,
         fallback: EmptyRouter::method_not_allowed(),
         _marker: PhantomData,
@@ -173,6 +176,7 @@ impl<H, B, T, F> OnMethod<H, B, T, F> {
     {
         OnMethod {
             method,
+            all_methods: method | self.all_methods,
             handler,
             fallback: self,
             _marker: PhantomData,
@@ -194,13 +198,14 @@ where
         Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, req: Request<B>) -> Self::Future {
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
         let req_method = req.method().clone();
 
         let fut = if self.method.matches(req.method()) {
             let fut = Handler::call(self.handler.clone(), req);
             Either::A { inner: fut }
         } else {
+            AllowedMethods::add(&mut req, self.method);
             let fut = self.fallback.clone().oneshot(req);
             Either::B { inner: fut }
         };
@@ -225,6 +230,15 @@ pub trait Handler<B, T>: Clone + Send + Sized + 'static {
     fn into_service(self) -> IntoService<Self, B, T> {
         IntoService::new(self)
     }
+
+    // wrap the handler in a tower middleware, the result is still a handler
+    // so it can be passed to `get`, `post` etc like any other
+    fn layer<L>(self, layer: L) -> Layered<L::Service, T>
+    where
+        L: tower_layer::Layer<IntoService<Self, B, T>>,
+    {
+        Layered::new(layer.layer(self.into_service()))
+    }
 }
 
 // #[async_trait]
@@ -248,68 +262,37 @@ pub trait Handler<B, T>: Clone + Send + Sized + 'static {
 //     }
 // }
 
-#[async_trait]
-impl<F, Fut, Res, B> Handler<B, ()> for F
-where
-    F: FnOnce() -> Fut + Clone + Send + Sync + 'static,
-    Fut: Future<Output = Res> + Send,
-    Res: IntoResponse,
-    B: Send + 'static,
-    // T: crate::extract::FromRequest<B> + Send,
-{
-    type Sealed = sealed::Hidden;
-    async fn call(self, req: Request<B>) -> Response<BoxBody> {
-        self().await.into_response().map(box_body)
-    }
-}
-
 macro_rules! impl_handler {
-    () => {
-
-    };
-
-    ($head:ident,$($tail:ident),* $(,)?) => {
-
+    ($($ty:ident),*) => {
         #[async_trait]
-        #[allow(non_snake_case)]
-        impl<F,Fut,B,Res:IntoResponse,$head,$($tail,)*>
-Handler<B,($head,$($tail,)*)>         for F
+        #[allow(non_snake_case, unused_mut, unused_variables)]
+        impl<F, Fut, B, Res, $($ty,)*> Handler<B, ($($ty,)*)> for F
         where
-        F:FnOnce($head,$($tail,)*) -> Fut + Clone + Send + Sync + 'static ,
-        Fut:Future<Output = Res> + Send,
-        B:Send + 'static,
-        $head:FromRequest<B> + Send,
-        $($tail:FromRequest<B> + Send,)*
+            F: FnOnce($($ty,)*) -> Fut + Clone + Send + Sync + 'static,
+            Fut: Future<Output = Res> + Send,
+            Res: IntoResponse,
+            B: Send + 'static,
+            $($ty: FromRequest<B> + Send,)*
         {
             type Sealed = sealed::Hidden;
 
-            async fn call(self,req:Request<B>) -> Response<BoxBody> {
+            async fn call(self, req: Request<B>) -> Response<BoxBody> {
                 let mut req = crate::extract::RequestParts::new(req);
 
-                let $head = match $head::from_request(&mut req).await {
-                    Ok(value) => value,
-                    Err(rejection) => return
-rejection.into_response().map(box_body)                 };
-
                 $(
-                    let $tail = match $tail::from_request(&mut req).await {
-                    Ok(value) => value,
-                    Err(rejection) => return
-rejection.into_response().map(box_body),                     };
+                    let $ty = match $ty::from_request(&mut req).await {
+                        Ok(value) => value,
+                        Err(rejection) => return rejection.into_response().map(box_body),
+                    };
                 )*
 
-                let res = self($head,$($tail,)*).await;
-
-                res.into_response().map(crate::body::box_body)
+                self($($ty,)*).await.into_response().map(box_body)
             }
         }
-
-        impl_handler!($($tail,)*);
-
     };
 }
 
-impl_handler!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15, T16);
+all_the_tuples!(impl_handler);
 
 pub struct Layered<S, T> {
     svc: S,
@@ -371,7 +354,7 @@ impl<S, T> Layered<S, T> {
     pub fn handle_error<F, ReqBody, ResBody, Res, E>(
         self,
         f: F,
-    ) -> Layered<HandleError<S, F, ReqBody>, E>
+    ) -> Layered<HandleError<S, F, ReqBody>, T>
     where
         S: Service<Request<ReqBody>, Response = Response<ResBody>>,
         F: FnOnce(S::Error) -> Result<Res, E>,
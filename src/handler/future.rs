@@ -1,7 +1,7 @@
 use std::{
     convert::Infallible,
     fmt,
-    future::{ready, Future},
+    future::Future,
     task::{Context, Poll},
 };
 
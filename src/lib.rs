@@ -9,12 +9,14 @@ pub mod body;
 mod error;
 pub mod extract;
 pub mod handler;
+pub mod middleware;
 pub mod response;
 pub mod router;
-mod service;
+pub mod serve;
+pub mod service;
 
 mod util;
 
-pub use self::router::Router;
+pub use self::{error::Error, router::Router, serve::serve};
 
 pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
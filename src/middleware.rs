@@ -0,0 +1,21 @@
+// Middleware that ships with nexus, usable with `Router::layer` and
+// `Handler::layer`.
+
+#[cfg(any(
+    feature = "compression-br",
+    feature = "compression-deflate",
+    feature = "compression-gzip",
+    feature = "compression-zstd"
+))]
+pub mod compression;
+pub mod cors;
+pub mod from_fn;
+pub mod metrics;
+pub mod trace;
+
+pub use self::{
+    cors::{AllowOrigin, CorsLayer},
+    from_fn::{from_fn, FromFn, FromFnLayer, Next},
+    metrics::{Metrics, MetricsLayer},
+    trace::TraceLayer,
+};
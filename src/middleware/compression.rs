@@ -0,0 +1,634 @@
+// Response compression.
+//
+// `CompressionLayer` picks the encoding with the highest q-value in the
+// `Accept-Encoding` header of the request among those enabled with the
+// `compression-br`, `compression-zstd`, `compression-gzip` and
+// `compression-deflate` features, preferring them in that order on ties.
+// The response body is compressed while it is streamed, it's never
+// buffered.
+//
+// Responses are sent as they are when they:
+//
+// - already have a `Content-Encoding` or are a range,
+// - have a body known to be smaller than `min_size`,
+// - have a content type that is already compressed, like images, or is a
+//   stream of events that must not be held back by the encoder.
+//
+// Other responses get `Vary: Accept-Encoding`, whether they were compressed
+// or not.
+//
+// `HEAD` requests are negotiated like `GET`, so both get the same headers.
+// The router has already emptied their body, so `min_size` is checked
+// against their `Content-Length` header when they have one, and the empty
+// body is sent as it is.
+
+use std::{
+    fmt, io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures_util::stream::Stream;
+use http::{header, HeaderMap, HeaderValue, Method, Request, Response, StatusCode};
+use pin_project_lite::pin_project;
+use sync_wrapper::SyncWrapper;
+use tokio::io::{AsyncBufRead, AsyncRead};
+use tokio_util::io::{ReaderStream, StreamReader};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    body::{box_body, BoxBody},
+    BoxError,
+};
+
+pub use async_compression::Level as CompressionLevel;
+
+const DEFAULT_MIN_SIZE: u64 = 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub struct CompressionLayer {
+    min_size: u64,
+    level: Option<CompressionLevel>,
+}
+
+impl CompressionLayer {
+    pub fn new() -> Self {
+        Self {
+            min_size: DEFAULT_MIN_SIZE,
+            level: None,
+        }
+    }
+
+    // Bodies with a known size below this many bytes aren't compressed,
+    // 1024 by default.
+    pub fn min_size(mut self, min_size: u64) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    // Compression level for every encoding. By default it's the default of
+    // each encoding, except for brotli which uses 4 as its default is too
+    // slow for responses compressed on the fly.
+    pub fn quality(mut self, level: CompressionLevel) -> Self {
+        self.level = Some(level);
+        self
+    }
+}
+
+impl Default for CompressionLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Layer<S> for CompressionLayer {
+    type Service = Compression<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Compression {
+            inner,
+            config: *self,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Compression<S> {
+    inner: S,
+    config: CompressionLayer,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Compression<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    ResBody: http_body::Body<Data = Bytes> + Send + Sync + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = CompressionFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        CompressionFuture {
+            encoding: Encoding::negotiate(req.headers()),
+            head: req.method() == Method::HEAD,
+            future: self.inner.call(req),
+            config: self.config,
+        }
+    }
+}
+
+pin_project! {
+    pub struct CompressionFuture<F> {
+        #[pin]
+        future: F,
+        // `None` if the client accepts none of the enabled encodings
+        encoding: Option<Encoding>,
+        head: bool,
+        config: CompressionLayer,
+    }
+}
+
+impl<F> fmt::Debug for CompressionFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressionFuture")
+            .field("encoding", &self.encoding)
+            .finish()
+    }
+}
+
+impl<F, ResBody, E> std::future::Future for CompressionFuture<F>
+where
+    F: std::future::Future<Output = Result<Response<ResBody>, E>>,
+    ResBody: http_body::Body<Data = Bytes> + Send + Sync + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Output = Result<Response<BoxBody>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let mut res = futures_util::ready!(this.future.poll(cx))?;
+
+        let size = if *this.head {
+            content_length(res.headers())
+        } else {
+            res.body().size_hint().exact()
+        };
+        if !should_compress(&res, size, this.config.min_size) {
+            return Poll::Ready(Ok(res.map(box_body)));
+        }
+
+        let headers = res.headers_mut();
+        let varies = headers
+            .get_all(header::VARY)
+            .iter()
+            .any(|vary| vary.as_bytes().eq_ignore_ascii_case(b"accept-encoding"));
+        if !varies {
+            headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+        }
+
+        let encoding = match *this.encoding {
+            Some(encoding) => encoding,
+            None => return Poll::Ready(Ok(res.map(box_body))),
+        };
+
+        headers.remove(header::CONTENT_LENGTH);
+        headers.remove(header::ACCEPT_RANGES);
+        headers.insert(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static(encoding.as_str()),
+        );
+        if *this.head {
+            return Poll::Ready(Ok(res.map(box_body)));
+        }
+
+        let level = this.config.level;
+        let res = res.map(|body| box_body(CompressionBody::new(body, encoding, level)));
+        Poll::Ready(Ok(res))
+    }
+}
+
+// `size` is the size of the body, if it's known
+fn should_compress<B>(res: &Response<B>, size: Option<u64>, min_size: u64) -> bool {
+    let status = res.status();
+    if status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+    {
+        return false;
+    }
+
+    let headers = res.headers();
+    if headers.contains_key(header::CONTENT_ENCODING) || headers.contains_key(header::CONTENT_RANGE)
+    {
+        return false;
+    }
+
+    if !is_compressible(headers.get(header::CONTENT_TYPE)) {
+        return false;
+    }
+
+    match size {
+        Some(size) => size >= min_size,
+        None => true,
+    }
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+fn is_compressible(content_type: Option<&HeaderValue>) -> bool {
+    let content_type = match content_type.and_then(|value| value.to_str().ok()) {
+        Some(content_type) => content_type.to_ascii_lowercase(),
+        None => return true,
+    };
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+
+    if essence == "image/svg+xml" {
+        return true;
+    }
+
+    !(essence.starts_with("image/")
+        || essence.starts_with("audio/")
+        || essence.starts_with("video/")
+        || matches!(
+            essence,
+            "application/gzip"
+                | "application/x-gzip"
+                | "application/zip"
+                | "application/zstd"
+                | "font/woff"
+                | "font/woff2"
+                | "text/event-stream"
+        ))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    #[cfg(feature = "compression-br")]
+    Br,
+    #[cfg(feature = "compression-zstd")]
+    Zstd,
+    #[cfg(feature = "compression-gzip")]
+    Gzip,
+    #[cfg(feature = "compression-deflate")]
+    Deflate,
+}
+
+impl Encoding {
+    // the enabled encodings, most preferred first
+    const ALL: &'static [Encoding] = &[
+        #[cfg(feature = "compression-br")]
+        Encoding::Br,
+        #[cfg(feature = "compression-zstd")]
+        Encoding::Zstd,
+        #[cfg(feature = "compression-gzip")]
+        Encoding::Gzip,
+        #[cfg(feature = "compression-deflate")]
+        Encoding::Deflate,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            #[cfg(feature = "compression-br")]
+            Encoding::Br => "br",
+            #[cfg(feature = "compression-zstd")]
+            Encoding::Zstd => "zstd",
+            #[cfg(feature = "compression-gzip")]
+            Encoding::Gzip => "gzip",
+            #[cfg(feature = "compression-deflate")]
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    // The enabled encoding with the highest q-value, `*` stands for any
+    // encoding that isn't listed.
+    fn negotiate(headers: &HeaderMap) -> Option<Self> {
+        let accepted = headers
+            .get_all(header::ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(parse_coding)
+            .collect::<Vec<_>>();
+
+        let q_value = |name: &str| {
+            accepted
+                .iter()
+                .find(|(coding, _)| coding.eq_ignore_ascii_case(name))
+                .map(|(_, q)| *q)
+        };
+
+        let mut best: Option<(Self, f32)> = None;
+        for &encoding in Self::ALL {
+            let q = q_value(encoding.as_str())
+                .or_else(|| q_value("*"))
+                .unwrap_or(0.0);
+            if q > 0.0 && best.is_none_or(|(_, best)| q > best) {
+                best = Some((encoding, q));
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+
+    fn encode<R>(self, reader: R, level: Option<CompressionLevel>) -> Pin<Box<dyn AsyncRead + Send>>
+    where
+        R: AsyncBufRead + Send + 'static,
+    {
+        use async_compression::tokio::bufread;
+
+        let level = level.unwrap_or(CompressionLevel::Default);
+        match self {
+            #[cfg(feature = "compression-br")]
+            Encoding::Br => {
+                let level = match level {
+                    CompressionLevel::Default => CompressionLevel::Precise(4),
+                    level => level,
+                };
+                Box::pin(bufread::BrotliEncoder::with_quality(reader, level))
+            }
+            #[cfg(feature = "compression-zstd")]
+            Encoding::Zstd => Box::pin(bufread::ZstdEncoder::with_quality(reader, level)),
+            #[cfg(feature = "compression-gzip")]
+            Encoding::Gzip => Box::pin(bufread::GzipEncoder::with_quality(reader, level)),
+            #[cfg(feature = "compression-deflate")]
+            Encoding::Deflate => Box::pin(bufread::ZlibEncoder::with_quality(reader, level)),
+        }
+    }
+}
+
+// `gzip;q=0.8` into its name and q-value, a missing q-value is 1
+fn parse_coding(coding: &str) -> Option<(&str, f32)> {
+    let mut parts = coding.split(';');
+    let name = parts.next()?.trim();
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut q = 1.0;
+    for param in parts {
+        let (key, value) = param.split_once('=')?;
+        if key.trim().eq_ignore_ascii_case("q") {
+            q = value.trim().parse::<f32>().ok()?.clamp(0.0, 1.0);
+        }
+    }
+    Some((name, q))
+}
+
+// The compressed body, read from the encoder as the inner body arrives.
+pub struct CompressionBody {
+    inner: SyncWrapper<ReaderStream<Pin<Box<dyn AsyncRead + Send>>>>,
+}
+
+impl CompressionBody {
+    fn new<B>(body: B, encoding: Encoding, level: Option<CompressionLevel>) -> Self
+    where
+        B: http_body::Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<BoxError>,
+    {
+        let reader = StreamReader::new(IntoStream { body });
+        Self {
+            inner: SyncWrapper::new(ReaderStream::new(encoding.encode(reader, level))),
+        }
+    }
+}
+
+impl fmt::Debug for CompressionBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressionBody").finish()
+    }
+}
+
+impl http_body::Body for CompressionBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(self.inner.get_mut()).poll_next(cx)
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Poll::Ready(Ok(None))
+    }
+}
+
+pin_project! {
+    // the data of a body as the stream `StreamReader` reads from
+    struct IntoStream<B> {
+        #[pin]
+        body: B,
+    }
+}
+
+impl<B> Stream for IntoStream<B>
+where
+    B: http_body::Body,
+    B::Error: Into<BoxError>,
+{
+    type Item = io::Result<B::Data>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.project()
+            .body
+            .poll_data(cx)
+            .map_err(|err| io::Error::other(err.into()))
+    }
+}
+
+#[cfg(all(test, feature = "compression-full"))]
+mod tests {
+    use async_compression::tokio::bufread::GzipDecoder;
+    use hyper::Body;
+    use tokio::io::AsyncReadExt;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        handler::{get, Handler},
+        Router,
+    };
+
+    fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ACCEPT_ENCODING,
+            HeaderValue::from_str(accept_encoding).unwrap(),
+        );
+        Encoding::negotiate(&headers)
+    }
+
+    #[test]
+    fn highest_q_value_wins() {
+        assert_eq!(negotiate("gzip;q=0.5, br;q=0.8"), Some(Encoding::Br));
+        assert_eq!(negotiate("br;q=0.1, deflate"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("GZIP"), Some(Encoding::Gzip));
+        // ties go to the preferred encoding
+        assert_eq!(negotiate("deflate, gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("identity"), None);
+        assert_eq!(Encoding::negotiate(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn wildcard() {
+        assert_eq!(negotiate("*"), Some(Encoding::Br));
+        assert_eq!(negotiate("*;q=0.5, gzip"), Some(Encoding::Gzip));
+        // a listed encoding isn't overridden by `*`
+        assert_eq!(negotiate("br;q=0.2, *;q=0.5"), Some(Encoding::Zstd));
+    }
+
+    #[test]
+    fn q_zero_excludes() {
+        assert_eq!(negotiate("gzip;q=0"), None);
+        assert_eq!(negotiate("br;q=0, zstd;q=0, *"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("*;q=0"), None);
+    }
+
+    const LARGE: &str = include_str!("compression.rs");
+
+    async fn send<H, T>(handler: H, method: Method, layer: CompressionLayer) -> Response<BoxBody>
+    where
+        H: Handler<Body, T> + Sync,
+        T: 'static,
+    {
+        let app = Router::new().route("/", get(handler)).layer(layer);
+        let req = Request::builder()
+            .method(method)
+            .uri("/")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(req).await.unwrap()
+    }
+
+    fn headers<const N: usize>(headers: [(header::HeaderName, &'static str); N]) -> HeaderMap {
+        headers
+            .into_iter()
+            .map(|(name, value)| (name, HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    fn vary(res: &Response<BoxBody>) -> Vec<&str> {
+        res.headers()
+            .get_all(header::VARY)
+            .iter()
+            .map(|value| value.to_str().unwrap())
+            .collect()
+    }
+
+    async fn gunzip(res: Response<BoxBody>) -> String {
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let mut decoded = String::new();
+        GzipDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .await
+            .unwrap();
+        decoded
+    }
+
+    #[tokio::test]
+    async fn gzip_round_trip() {
+        let res = send(|| async { LARGE }, Method::GET, CompressionLayer::new()).await;
+
+        assert_eq!(res.headers()[header::CONTENT_ENCODING], "gzip");
+        assert!(!res.headers().contains_key(header::CONTENT_LENGTH));
+        assert_eq!(vary(&res), ["accept-encoding"]);
+        assert_eq!(gunzip(res).await, LARGE);
+    }
+
+    #[tokio::test]
+    async fn min_size() {
+        // never compressed whatever the request accepts, so it doesn't vary
+        let res = send(|| async { "small" }, Method::GET, CompressionLayer::new()).await;
+        assert!(!res.headers().contains_key(header::CONTENT_ENCODING));
+        assert!(vary(&res).is_empty());
+
+        let layer = CompressionLayer::new().min_size(0);
+        let res = send(|| async { "small" }, Method::GET, layer).await;
+        assert_eq!(res.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(gunzip(res).await, "small");
+    }
+
+    #[tokio::test]
+    async fn skips_encoded_and_ranges() {
+        let encoded = || async { (headers([(header::CONTENT_ENCODING, "br")]), LARGE) };
+        let res = send(encoded, Method::GET, CompressionLayer::new()).await;
+        assert_eq!(res.headers()[header::CONTENT_ENCODING], "br");
+        assert!(vary(&res).is_empty());
+
+        let range = || async {
+            (
+                StatusCode::PARTIAL_CONTENT,
+                headers([(header::CONTENT_RANGE, "bytes 0-1023/4096")]),
+                &LARGE[..1024],
+            )
+        };
+        let res = send(range, Method::GET, CompressionLayer::new()).await;
+        assert!(!res.headers().contains_key(header::CONTENT_ENCODING));
+        assert!(vary(&res).is_empty());
+    }
+
+    #[tokio::test]
+    async fn skips_compressed_content_types() {
+        for content_type in [
+            "image/png",
+            "video/mp4",
+            "application/zip",
+            "text/event-stream",
+        ] {
+            let handler = move || async move {
+                let mut headers = HeaderMap::new();
+                headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+                (headers, LARGE)
+            };
+            let res = send(handler, Method::GET, CompressionLayer::new()).await;
+            assert!(
+                !res.headers().contains_key(header::CONTENT_ENCODING),
+                "{}",
+                content_type
+            );
+        }
+
+        let svg = || async { (headers([(header::CONTENT_TYPE, "image/svg+xml")]), LARGE) };
+        let res = send(svg, Method::GET, CompressionLayer::new()).await;
+        assert_eq!(res.headers()[header::CONTENT_ENCODING], "gzip");
+    }
+
+    #[tokio::test]
+    async fn merges_vary() {
+        let origin = || async { (headers([(header::VARY, "origin")]), LARGE) };
+        let res = send(origin, Method::GET, CompressionLayer::new()).await;
+        assert_eq!(vary(&res), ["origin", "accept-encoding"]);
+
+        let already = || async { (headers([(header::VARY, "Accept-Encoding")]), LARGE) };
+        let res = send(already, Method::GET, CompressionLayer::new()).await;
+        assert_eq!(vary(&res), ["Accept-Encoding"]);
+    }
+
+    #[tokio::test]
+    async fn head_like_get() {
+        let res = send(|| async { LARGE }, Method::HEAD, CompressionLayer::new()).await;
+        assert_eq!(res.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(vary(&res), ["accept-encoding"]);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(body.is_empty());
+
+        // a small body is only known from its `Content-Length`
+        let small = || async { (headers([(header::CONTENT_LENGTH, "5")]), "small") };
+        let res = send(small, Method::HEAD, CompressionLayer::new()).await;
+        assert!(!res.headers().contains_key(header::CONTENT_ENCODING));
+        assert!(vary(&res).is_empty());
+    }
+
+    #[tokio::test]
+    async fn not_acceptable_encoding() {
+        let app = Router::new()
+            .route("/", get(|| async { LARGE }))
+            .layer(CompressionLayer::new());
+        let req = Request::builder()
+            .uri("/")
+            .header(header::ACCEPT_ENCODING, "identity")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert!(!res.headers().contains_key(header::CONTENT_ENCODING));
+        assert_eq!(vary(&res), ["accept-encoding"]);
+    }
+}
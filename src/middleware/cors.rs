@@ -0,0 +1,634 @@
+// Cross-Origin Resource Sharing.
+//
+// `CorsLayer` answers preflight requests itself, before they reach the
+// router, so a route that only has `get(...)` doesn't reject them with
+// `405 Method Not Allowed`. Other requests from an allowed origin are passed
+// on and the CORS headers are added to their response. Requests without an
+// `Origin` header only get `Vary: origin`, so caches don't hand their
+// response to a cross-origin request.
+//
+//     Router::new()
+//         .route("/users", get(list_users).post(create_user))
+//         .layer(
+//             CorsLayer::new()
+//                 .allow_origin(AllowOrigin::exact(HeaderValue::from_static("https://example.com")))
+//                 .allow_methods(MethodFilter::GET | MethodFilter::POST)
+//                 .allow_headers([CONTENT_TYPE]),
+//         )
+//
+// Nothing is allowed by default, an origin has to be set for the layer to
+// do anything.
+
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use http::{
+    header::{self, HeaderName},
+    HeaderMap, HeaderValue, Method, Request, Response, StatusCode,
+};
+use pin_project_lite::pin_project;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    body::{self, box_body, BoxBody},
+    router::method_filter::MethodFilter,
+    BoxError,
+};
+
+// Origins whose requests are allowed.
+#[derive(Clone)]
+pub struct AllowOrigin(OriginInner);
+
+#[derive(Clone)]
+enum OriginInner {
+    Any,
+    List(Vec<HeaderValue>),
+    Predicate(Arc<dyn Fn(&HeaderValue) -> bool + Send + Sync>),
+}
+
+impl AllowOrigin {
+    // any origin, sent as `*`
+    pub fn any() -> Self {
+        Self(OriginInner::Any)
+    }
+
+    pub fn exact(origin: HeaderValue) -> Self {
+        Self::list([origin])
+    }
+
+    pub fn list<I>(origins: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderValue>,
+    {
+        Self(OriginInner::List(origins.into_iter().collect()))
+    }
+
+    // origins for which `f` returns `true`, such as all subdomains
+    pub fn predicate<F>(f: F) -> Self
+    where
+        F: Fn(&HeaderValue) -> bool + Send + Sync + 'static,
+    {
+        Self(OriginInner::Predicate(Arc::new(f)))
+    }
+
+    fn is_any(&self) -> bool {
+        matches!(self.0, OriginInner::Any)
+    }
+
+    // value of `Access-Control-Allow-Origin` for a request from `origin`
+    fn allow(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        match &self.0 {
+            OriginInner::Any => Some(HeaderValue::from_static("*")),
+            OriginInner::List(origins) => origins.contains(origin).then(|| origin.clone()),
+            OriginInner::Predicate(f) => f(origin).then(|| origin.clone()),
+        }
+    }
+}
+
+impl fmt::Debug for AllowOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            OriginInner::Any => f.write_str("Any"),
+            OriginInner::List(origins) => f.debug_tuple("List").field(origins).finish(),
+            OriginInner::Predicate(_) => f.write_str("Predicate"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum AllowHeaders {
+    List(Option<HeaderValue>),
+    // whatever the preflight asks for
+    Mirror,
+}
+
+#[derive(Debug, Clone)]
+pub struct CorsLayer {
+    allow_origin: Option<AllowOrigin>,
+    allow_methods: MethodFilter,
+    allow_headers: AllowHeaders,
+    allow_credentials: bool,
+    expose_headers: Option<HeaderValue>,
+    max_age: Option<Duration>,
+}
+
+impl CorsLayer {
+    // no origins, `GET`, `HEAD` and `POST`, and no extra headers
+    pub fn new() -> Self {
+        Self {
+            allow_origin: None,
+            allow_methods: MethodFilter::GET | MethodFilter::HEAD | MethodFilter::POST,
+            allow_headers: AllowHeaders::List(None),
+            allow_credentials: false,
+            expose_headers: None,
+            max_age: None,
+        }
+    }
+
+    pub fn allow_origin(mut self, origin: AllowOrigin) -> Self {
+        self.allow_origin = Some(origin);
+        self
+    }
+
+    pub fn allow_methods(mut self, methods: MethodFilter) -> Self {
+        self.allow_methods = methods;
+        self
+    }
+
+    // request headers besides the CORS-safelisted ones
+    pub fn allow_headers<I>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        self.allow_headers = AllowHeaders::List(join(headers));
+        self
+    }
+
+    // allow any request header by echoing `Access-Control-Request-Headers`
+    pub fn allow_any_header(mut self) -> Self {
+        self.allow_headers = AllowHeaders::Mirror;
+        self
+    }
+
+    // Let the browser send cookies and read responses to credentialed
+    // requests. Can't be combined with `AllowOrigin::any`.
+    pub fn allow_credentials(mut self, allow: bool) -> Self {
+        self.allow_credentials = allow;
+        self
+    }
+
+    // response headers besides the CORS-safelisted ones scripts can read
+    pub fn expose_headers<I>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        self.expose_headers = join(headers);
+        self
+    }
+
+    // how long the browser may cache the result of a preflight
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+}
+
+impl Default for CorsLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn join<I>(headers: I) -> Option<HeaderValue>
+where
+    I: IntoIterator<Item = HeaderName>,
+{
+    let headers = headers
+        .into_iter()
+        .map(|name| name.as_str().to_owned())
+        .collect::<Vec<_>>();
+
+    if headers.is_empty() {
+        return None;
+    }
+    Some(HeaderValue::from_str(&headers.join(",")).expect("header names are valid header values"))
+}
+
+impl<S> Layer<S> for CorsLayer {
+    type Service = Cors<S>;
+
+    // Panics if credentials are allowed for any origin, browsers reject
+    // that combination.
+    fn layer(&self, inner: S) -> Self::Service {
+        assert!(
+            !(self.allow_credentials
+                && self.allow_origin.as_ref().is_some_and(AllowOrigin::is_any)),
+            "CORS credentials can't be allowed together with `AllowOrigin::any()`"
+        );
+
+        Cors {
+            inner,
+            config: Arc::new(self.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cors<S> {
+    inner: S,
+    config: Arc<CorsLayer>,
+}
+
+impl<S> Cors<S> {
+    // The response depends on the origin unless every origin is allowed,
+    // with or without an `Origin` header in the request.
+    fn vary(&self) -> Vec<(HeaderName, HeaderValue)> {
+        match &self.config.allow_origin {
+            Some(allow_origin) if !allow_origin.is_any() => {
+                vec![(header::VARY, HeaderValue::from_static("origin"))]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // headers added to every response to a request from `origin`
+    fn response_headers(&self, origin: &HeaderValue) -> Vec<(HeaderName, HeaderValue)> {
+        let mut headers = self.vary();
+
+        let allow_origin = match &self.config.allow_origin {
+            Some(allow_origin) => allow_origin,
+            None => return headers,
+        };
+        let origin = match allow_origin.allow(origin) {
+            Some(origin) => origin,
+            None => return headers,
+        };
+        headers.push((header::ACCESS_CONTROL_ALLOW_ORIGIN, origin));
+
+        if self.config.allow_credentials {
+            headers.push((
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            ));
+        }
+        if let Some(expose) = &self.config.expose_headers {
+            headers.push((header::ACCESS_CONTROL_EXPOSE_HEADERS, expose.clone()));
+        }
+
+        headers
+    }
+
+    fn preflight(&self, origin: &HeaderValue, req_headers: &HeaderMap) -> Response<BoxBody> {
+        let mut res = Response::new(body::empty());
+        *res.status_mut() = StatusCode::OK;
+        let headers = res.headers_mut();
+
+        let mut allowed = false;
+        for (name, value) in self.response_headers(origin) {
+            allowed |= name == header::ACCESS_CONTROL_ALLOW_ORIGIN;
+            if name != header::ACCESS_CONTROL_EXPOSE_HEADERS {
+                headers.append(name, value);
+            }
+        }
+
+        if let AllowHeaders::Mirror = self.config.allow_headers {
+            headers.append(
+                header::VARY,
+                HeaderValue::from_static("access-control-request-headers"),
+            );
+        }
+
+        // without an allowed origin the browser fails the preflight
+        if !allowed {
+            return res;
+        }
+
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            self.config.allow_methods.to_allow_header(),
+        );
+
+        let allow_headers = match &self.config.allow_headers {
+            AllowHeaders::List(allow_headers) => allow_headers.clone(),
+            AllowHeaders::Mirror => req_headers
+                .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+                .cloned(),
+        };
+        if let Some(allow_headers) = allow_headers {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, allow_headers);
+        }
+
+        if let Some(max_age) = self.config.max_age {
+            headers.insert(
+                header::ACCESS_CONTROL_MAX_AGE,
+                HeaderValue::from(max_age.as_secs()),
+            );
+        }
+
+        res
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Cors<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    ResBody: http_body::Body<Data = Bytes> + Send + Sync + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = CorsFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let origin = match req.headers().get(header::ORIGIN) {
+            Some(origin) => origin.clone(),
+            None => {
+                return CorsFuture {
+                    future: Some(self.inner.call(req)),
+                    preflight: None,
+                    headers: self.vary(),
+                }
+            }
+        };
+
+        let is_preflight = req.method() == Method::OPTIONS
+            && req
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+        if is_preflight {
+            return CorsFuture {
+                future: None,
+                preflight: Some(self.preflight(&origin, req.headers())),
+                headers: Vec::new(),
+            };
+        }
+
+        CorsFuture {
+            headers: self.response_headers(&origin),
+            future: Some(self.inner.call(req)),
+            preflight: None,
+        }
+    }
+}
+
+pin_project! {
+    pub struct CorsFuture<F> {
+        #[pin]
+        future: Option<F>,
+        preflight: Option<Response<BoxBody>>,
+        // added to the response of `future`
+        headers: Vec<(HeaderName, HeaderValue)>,
+    }
+}
+
+impl<F> fmt::Debug for CorsFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CorsFuture").finish()
+    }
+}
+
+impl<F, ResBody, E> Future for CorsFuture<F>
+where
+    F: Future<Output = Result<Response<ResBody>, E>>,
+    ResBody: http_body::Body<Data = Bytes> + Send + Sync + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Output = Result<Response<BoxBody>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        let future = match this.future.as_pin_mut() {
+            Some(future) => future,
+            None => {
+                let res = this
+                    .preflight
+                    .take()
+                    .expect("future polled after completion");
+                return Poll::Ready(Ok(res));
+            }
+        };
+
+        let mut res = futures_util::ready!(future.poll(cx))?;
+        let headers = res.headers_mut();
+        for (name, value) in this.headers.drain(..) {
+            if name == header::VARY {
+                headers.append(name, value);
+            } else {
+                headers.insert(name, value);
+            }
+        }
+
+        Poll::Ready(Ok(res.map(box_body)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use http::header::{ACCESS_CONTROL_REQUEST_METHOD, CONTENT_TYPE, ORIGIN};
+    use hyper::Body;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{handler::get, Router};
+
+    const ORIGIN_A: &str = "https://a.example";
+
+    fn app(
+        cors: CorsLayer,
+    ) -> impl Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible> + Clone {
+        Router::new()
+            .route("/", get(|| async { "hello" }))
+            .layer(cors)
+    }
+
+    fn cors() -> CorsLayer {
+        CorsLayer::new().allow_origin(AllowOrigin::exact(HeaderValue::from_static(ORIGIN_A)))
+    }
+
+    async fn send<S>(app: S, req: Request<Body>) -> Response<BoxBody>
+    where
+        S: Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible>,
+    {
+        app.oneshot(req).await.unwrap()
+    }
+
+    fn get_from(origin: &str) -> Request<Body> {
+        Request::builder()
+            .uri("/")
+            .header(ORIGIN, origin)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn preflight(origin: &str) -> Request<Body> {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/")
+            .header(ORIGIN, origin)
+            .header(ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn vary(res: &Response<BoxBody>) -> Vec<&str> {
+        res.headers()
+            .get_all(header::VARY)
+            .iter()
+            .map(|value| value.to_str().unwrap())
+            .collect()
+    }
+
+    // the route only has `get(...)`, the router would answer OPTIONS with 405
+    #[tokio::test]
+    async fn preflight_skips_router() {
+        let app = app(cors()
+            .allow_methods(MethodFilter::GET | MethodFilter::POST)
+            .allow_headers([CONTENT_TYPE]));
+
+        let res = send(app, preflight(ORIGIN_A)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let headers = res.headers();
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], ORIGIN_A);
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_METHODS], "GET,POST");
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "content-type"
+        );
+        assert!(!headers.contains_key(header::ALLOW));
+    }
+
+    #[tokio::test]
+    async fn disallowed_origin() {
+        let app = app(cors());
+
+        let res = send(app.clone(), preflight("https://evil.example")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert!(!res
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_METHODS));
+        assert_eq!(vary(&res), ["origin"]);
+
+        // the request still reaches the handler, the browser hides the response
+        let res = send(app, get_from("https://evil.example")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert_eq!(vary(&res), ["origin"]);
+    }
+
+    #[tokio::test]
+    async fn no_origin_still_varies() {
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let res = send(app(cors()), req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert_eq!(vary(&res), ["origin"]);
+
+        // with any origin allowed the response is the same for everyone
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let res = send(app(CorsLayer::new().allow_origin(AllowOrigin::any())), req).await;
+        assert!(vary(&res).is_empty());
+    }
+
+    #[tokio::test]
+    async fn any_origin() {
+        let app = app(CorsLayer::new().allow_origin(AllowOrigin::any()));
+
+        let res = send(app, get_from(ORIGIN_A)).await;
+        assert_eq!(res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(vary(&res).is_empty());
+    }
+
+    #[tokio::test]
+    async fn predicate() {
+        let app = app(
+            CorsLayer::new().allow_origin(AllowOrigin::predicate(|origin| {
+                origin.as_bytes().ends_with(b".example.com")
+            })),
+        );
+
+        let res = send(app.clone(), get_from("https://api.example.com")).await;
+        assert_eq!(
+            res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://api.example.com"
+        );
+
+        let res = send(app, get_from("https://example.org")).await;
+        assert!(!res
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "CORS credentials can't be allowed together with `AllowOrigin::any()`"
+    )]
+    fn credentials_with_any_origin() {
+        let _ = app(CorsLayer::new()
+            .allow_origin(AllowOrigin::any())
+            .allow_credentials(true));
+    }
+
+    #[tokio::test]
+    async fn credentials() {
+        let app = app(cors().allow_credentials(true));
+
+        let res = send(app, get_from(ORIGIN_A)).await;
+        assert_eq!(
+            res.headers()[header::ACCESS_CONTROL_ALLOW_CREDENTIALS],
+            "true"
+        );
+    }
+
+    #[tokio::test]
+    async fn max_age_and_exposed_headers() {
+        let app = app(cors()
+            .max_age(Duration::from_secs(600))
+            .expose_headers([header::ETAG, header::LINK]));
+
+        // exposed headers are for actual responses, max-age for preflights
+        let res = send(app.clone(), preflight(ORIGIN_A)).await;
+        assert_eq!(res.headers()[header::ACCESS_CONTROL_MAX_AGE], "600");
+        assert!(!res
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_EXPOSE_HEADERS));
+
+        let res = send(app, get_from(ORIGIN_A)).await;
+        assert_eq!(
+            res.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS],
+            "etag,link"
+        );
+        assert!(!res.headers().contains_key(header::ACCESS_CONTROL_MAX_AGE));
+    }
+
+    #[tokio::test]
+    async fn mirrors_request_headers() {
+        let app = app(cors().allow_any_header());
+
+        let res = send(app, preflight(ORIGIN_A)).await;
+        assert_eq!(
+            res.headers()[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "content-type"
+        );
+        assert_eq!(vary(&res), ["origin", "access-control-request-headers"]);
+    }
+
+    #[tokio::test]
+    async fn merges_vary() {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|| async {
+                    let mut headers = HeaderMap::new();
+                    headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+                    (headers, "hello")
+                }),
+            )
+            .layer(cors());
+
+        let res = send(app, get_from(ORIGIN_A)).await;
+        assert_eq!(vary(&res), ["accept-encoding", "origin"]);
+    }
+}
@@ -0,0 +1,325 @@
+// Middleware written as an async function.
+//
+// The function takes any number of extractors, then the request and `Next`,
+// and returns anything that implements `IntoResponse`:
+//
+//     async fn auth(TypedHeader(token): TypedHeader<Authorization<Bearer>>,
+//                   req: Request<Body>, next: Next<Body>) -> impl IntoResponse
+//
+// Calling `next.run(req)` passes the request on to the wrapped service,
+// returning early without calling it short-circuits the request. Extractors
+// run on the request before the function is called, like they do for
+// handlers, so they can't take the body or the request couldn't be passed on.
+
+use std::{
+    convert::Infallible,
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use http::{Request, Response};
+use tower::util::{BoxCloneService, ServiceExt};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    body::{box_body, BoxBody},
+    extract::{rejection::RequestAlreadyExtracted, FromRequest, RequestParts},
+    response::IntoResponse,
+    BoxError,
+};
+
+pub fn from_fn<F, T>(f: F) -> FromFnLayer<F, T> {
+    FromFnLayer {
+        f,
+        _extractors: PhantomData,
+    }
+}
+
+pub struct FromFnLayer<F, T> {
+    f: F,
+    _extractors: PhantomData<fn() -> T>,
+}
+
+impl<F, T> Clone for FromFnLayer<F, T>
+where
+    F: Clone,
+{
+    fn clone(&self) -> Self {
+        from_fn(self.f.clone())
+    }
+}
+
+impl<F, T> fmt::Debug for FromFnLayer<F, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FromFnLayer")
+            .field("f", &std::any::type_name::<F>())
+            .finish()
+    }
+}
+
+impl<S, F, T> Layer<S> for FromFnLayer<F, T>
+where
+    F: Clone,
+{
+    type Service = FromFn<F, S, T>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        FromFn {
+            f: self.f.clone(),
+            inner,
+            _extractors: PhantomData,
+        }
+    }
+}
+
+pub struct FromFn<F, S, T> {
+    f: F,
+    inner: S,
+    _extractors: PhantomData<fn() -> T>,
+}
+
+impl<F, S, T> Clone for FromFn<F, S, T>
+where
+    F: Clone,
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            f: self.f.clone(),
+            inner: self.inner.clone(),
+            _extractors: PhantomData,
+        }
+    }
+}
+
+impl<F, S, T> fmt::Debug for FromFn<F, S, T>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FromFn")
+            .field("f", &std::any::type_name::<F>())
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+macro_rules! impl_from_fn {
+    ($($ty:ident),*) => {
+        #[allow(non_snake_case, unused_mut)]
+        impl<F, Fut, Out, S, ReqBody, ResBody, $($ty,)*> Service<Request<ReqBody>>
+            for FromFn<F, S, ($($ty,)*)>
+        where
+            F: FnOnce($($ty,)* Request<ReqBody>, Next<ReqBody>) -> Fut + Clone + Send + 'static,
+            $($ty: FromRequest<ReqBody> + Send,)*
+            Fut: Future<Output = Out> + Send + 'static,
+            Out: IntoResponse,
+            S: Service<Request<ReqBody>, Response = Response<ResBody>, Error = Infallible>
+                + Clone
+                + Send
+                + 'static,
+            S::Future: Send + 'static,
+            ReqBody: Send + 'static,
+            ResBody: http_body::Body<Data = Bytes> + Send + Sync + 'static,
+            ResBody::Error: Into<BoxError>,
+        {
+            type Response = Response<BoxBody>;
+            type Error = Infallible;
+            type Future = FromFnFuture;
+
+            fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+                let f = self.f.clone();
+                let inner = self.inner.clone();
+
+                let future = Box::pin(async move {
+                    let mut req = RequestParts::new(req);
+
+                    $(
+                        let $ty = match $ty::from_request(&mut req).await {
+                            Ok(value) => value,
+                            Err(rejection) => return Ok(rejection.into_response().map(box_body)),
+                        };
+                    )*
+
+                    let req = match req.try_into_request() {
+                        Ok(req) => req,
+                        Err(err) => match err.downcast::<RequestAlreadyExtracted>() {
+                            Ok(rejection) => return Ok(rejection.into_response().map(box_body)),
+                            Err(err) => unreachable!(
+                                "Unexpected error type from `try_into_request`: `{:?}`. This is a bug in nexus, please file an issue",
+                                err
+                            ),
+                        },
+                    };
+
+                    let next = Next {
+                        inner: BoxCloneService::new(inner.map_response(|res| res.map(box_body))),
+                    };
+
+                    Ok(f($($ty,)* req, next).await.into_response().map(box_body))
+                });
+
+                FromFnFuture { future }
+            }
+        }
+    };
+}
+
+all_the_tuples!(impl_from_fn);
+
+// The rest of the middleware stack and the handler.
+pub struct Next<ReqBody> {
+    inner: BoxCloneService<Request<ReqBody>, Response<BoxBody>, Infallible>,
+}
+
+impl<ReqBody> Next<ReqBody> {
+    pub async fn run(self, req: Request<ReqBody>) -> Response<BoxBody> {
+        match self.inner.oneshot(req).await {
+            Ok(res) => res,
+            Err(err) => match err {},
+        }
+    }
+}
+
+impl<ReqBody> fmt::Debug for Next<ReqBody> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Next").finish()
+    }
+}
+
+opaque_future! {
+    pub type FromFnFuture = BoxFuture<'static, Result<Response<BoxBody>, Infallible>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use http::StatusCode;
+    use hyper::Body;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::{
+        extract::builtin::Query,
+        handler::{get, Handler},
+        Router,
+    };
+
+    #[derive(Deserialize)]
+    struct Auth {
+        token: String,
+    }
+
+    async fn require_token(
+        Query(auth): Query<Auth>,
+        req: Request<Body>,
+        next: Next<Body>,
+    ) -> Result<Response<BoxBody>, StatusCode> {
+        if auth.token != "secret" {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        Ok(next.run(req).await)
+    }
+
+    // a handler counting how often it's called
+    fn counted() -> (impl Handler<Body, ()> + Sync, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler = {
+            let calls = calls.clone();
+            move || async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                "admin"
+            }
+        };
+        (handler, calls)
+    }
+
+    fn request(token: Option<&str>) -> Request<Body> {
+        let uri = match token {
+            Some(token) => format!("/admin?token={}", token),
+            None => "/admin".to_owned(),
+        };
+        Request::builder().uri(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn short_circuits_with_error() {
+        let (handler, calls) = counted();
+        let app = Router::new()
+            .route("/admin", get(handler))
+            .layer(from_fn(require_token));
+
+        let res = app.clone().oneshot(request(Some("wrong"))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        let res = app.oneshot(request(Some("secret"))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body, "admin");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn rejection_skips_next() {
+        let (handler, calls) = counted();
+        let app = Router::new()
+            .route("/admin", get(handler))
+            .layer(from_fn(require_token));
+
+        let res = app.oneshot(request(None)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn handler_layer() {
+        let (handler, calls) = counted();
+        let app = Router::new()
+            .route("/admin", get(handler.layer(from_fn(require_token))))
+            .route("/public", get(|| async { "public" }));
+
+        let res = app.clone().oneshot(request(None)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let res = app.clone().oneshot(request(Some("secret"))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // only the handler is wrapped
+        let req = Request::builder()
+            .uri("/public")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn runs_after_next() {
+        let app = Router::new()
+            .route("/", get(|| async { "hello" }))
+            .layer(from_fn(|req: Request<Body>, next: Next<Body>| async move {
+                let mut res = next.run(req).await;
+                res.headers_mut()
+                    .insert("x-middleware", http::HeaderValue::from_static("ran"));
+                res
+            }));
+
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.headers()["x-middleware"], "ran");
+    }
+}
@@ -0,0 +1,595 @@
+// Prometheus metrics.
+//
+// `MetricsLayer` records the number of requests, a histogram of their
+// latency and the number of requests in flight into a `Metrics` registry,
+// which renders them in the Prometheus text format:
+//
+//     let metrics = Metrics::new();
+//     let app = Router::new()
+//         .route("/metrics", get(metrics.handler()))
+//         .layer(metrics.layer());
+//
+// Requests are labelled with their method, the route pattern that matched
+// and the class of the status (`2xx`, `4xx`, ...), never with the raw path,
+// so the number of series stays bounded. Requests that no route matched are
+// labelled with `route="unmatched"`. A request is done once its response
+// body has been sent or dropped, so latency includes streaming the body.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use bytes::Bytes;
+use http::{header, HeaderMap, HeaderValue, Method, Request, Response, StatusCode};
+use http_body::Full;
+use pin_project_lite::pin_project;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{extract::request_parts::MatchedPath, response::IntoResponse};
+
+// the default buckets of the Prometheus client libraries, in seconds
+const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+// Registry of the recorded metrics. Clones share the same registry.
+//
+// It can be returned from a handler to render the metrics.
+#[derive(Clone)]
+pub struct Metrics {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    buckets: Vec<f64>,
+    requests: Mutex<BTreeMap<Labels, Series>>,
+    in_flight: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Labels {
+    method: &'static str,
+    route: Arc<str>,
+    status: &'static str,
+}
+
+struct Series {
+    count: u64,
+    sum: f64,
+    // requests per bucket, made cumulative when rendering
+    buckets: Vec<u64>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::with_buckets(DEFAULT_BUCKETS.to_vec())
+    }
+
+    // Upper bounds of the latency histogram buckets, in seconds.
+    //
+    // Panics if a bound isn't finite.
+    pub fn with_buckets(mut buckets: Vec<f64>) -> Self {
+        assert!(
+            buckets.iter().all(|bound| bound.is_finite()),
+            "histogram buckets must be finite"
+        );
+        buckets.sort_by(f64::total_cmp);
+        buckets.dedup();
+
+        Self {
+            inner: Arc::new(Inner {
+                buckets,
+                requests: Mutex::new(BTreeMap::new()),
+                in_flight: Mutex::new(BTreeMap::new()),
+            }),
+        }
+    }
+
+    pub fn layer(&self) -> MetricsLayer {
+        MetricsLayer {
+            metrics: self.clone(),
+        }
+    }
+
+    // handler rendering the metrics, for `get(metrics.handler())`
+    pub fn handler(
+        &self,
+    ) -> impl FnOnce() -> std::future::Ready<Metrics> + Clone + Send + Sync + 'static {
+        let metrics = self.clone();
+        move || std::future::ready(metrics)
+    }
+
+    // the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut buf = String::new();
+        let requests = self.inner.requests.lock().unwrap();
+
+        buf.push_str("# HELP http_requests_total Total number of HTTP requests.\n");
+        buf.push_str("# TYPE http_requests_total counter\n");
+        for (labels, series) in requests.iter() {
+            let _ = writeln!(buf, "http_requests_total{{{}}} {}", labels, series.count);
+        }
+
+        buf.push_str("# HELP http_request_duration_seconds HTTP request latency in seconds.\n");
+        buf.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for (labels, series) in requests.iter() {
+            let mut cumulative = 0;
+            for (bound, count) in self.inner.buckets.iter().zip(&series.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    buf,
+                    "http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                buf,
+                "http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, series.count
+            );
+            let _ = writeln!(
+                buf,
+                "http_request_duration_seconds_sum{{{}}} {}",
+                labels, series.sum
+            );
+            let _ = writeln!(
+                buf,
+                "http_request_duration_seconds_count{{{}}} {}",
+                labels, series.count
+            );
+        }
+        drop(requests);
+
+        buf.push_str("# HELP http_requests_in_flight Number of HTTP requests being served.\n");
+        buf.push_str("# TYPE http_requests_in_flight gauge\n");
+        for (method, count) in self.inner.in_flight.lock().unwrap().iter() {
+            let _ = writeln!(
+                buf,
+                "http_requests_in_flight{{method=\"{}\"}} {}",
+                method, count
+            );
+        }
+
+        buf
+    }
+
+    fn start(&self, method: &'static str) {
+        *self
+            .inner
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(method)
+            .or_default() += 1;
+    }
+
+    fn finish(&self, method: &'static str, done: Option<(Arc<str>, &'static str)>, seconds: f64) {
+        if let Some(count) = self.inner.in_flight.lock().unwrap().get_mut(method) {
+            *count -= 1;
+        }
+
+        // the request was cancelled before there was a response
+        let (route, status) = match done {
+            Some(done) => done,
+            None => return,
+        };
+
+        let labels = Labels {
+            method,
+            route,
+            status,
+        };
+        let mut requests = self.inner.requests.lock().unwrap();
+        let series = requests.entry(labels).or_insert_with(|| Series {
+            count: 0,
+            sum: 0.0,
+            buckets: vec![0; self.inner.buckets.len()],
+        });
+        series.count += 1;
+        series.sum += seconds;
+        if let Some(bucket) = self
+            .inner
+            .buckets
+            .iter()
+            .position(|bound| seconds <= *bound)
+        {
+            series.buckets[bucket] += 1;
+        }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics")
+            .field("buckets", &self.inner.buckets)
+            .finish()
+    }
+}
+
+impl IntoResponse for Metrics {
+    type Body = Full<Bytes>;
+    type BodyError = std::convert::Infallible;
+
+    fn into_response(self) -> Response<Self::Body> {
+        let mut res = Response::new(Full::from(self.render()));
+        res.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8"),
+        );
+        res
+    }
+}
+
+impl fmt::Display for Labels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "method=\"{}\",route=\"", self.method)?;
+        for c in self.route.chars() {
+            match c {
+                '\\' => f.write_str("\\\\")?,
+                '"' => f.write_str("\\\"")?,
+                '\n' => f.write_str("\\n")?,
+                c => f.write_char(c)?,
+            }
+        }
+        write!(f, "\",status=\"{}\"", self.status)
+    }
+}
+
+// methods outside of the standard ones are grouped so clients can't create
+// new series
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::CONNECT => "CONNECT",
+        Method::OPTIONS => "OPTIONS",
+        Method::TRACE => "TRACE",
+        Method::PATCH => "PATCH",
+        _ => "OTHER",
+    }
+}
+
+fn status_label(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MetricsLayer {
+    metrics: Metrics,
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = RecordMetrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RecordMetrics {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordMetrics<S> {
+    inner: S,
+    metrics: Metrics,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for RecordMetrics<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+{
+    type Response = Response<MetricsBody<ResBody>>;
+    type Error = S::Error;
+    type Future = MetricsFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let method = method_label(req.method());
+        self.metrics.start(method);
+
+        MetricsFuture {
+            future: self.inner.call(req),
+            recording: Some(Recording {
+                metrics: self.metrics.clone(),
+                method,
+                done: None,
+                start: Instant::now(),
+            }),
+        }
+    }
+}
+
+// An in-flight request, recorded when dropped.
+struct Recording {
+    metrics: Metrics,
+    method: &'static str,
+    // route and status class, once there is a response
+    done: Option<(Arc<str>, &'static str)>,
+    start: Instant,
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        let seconds = self.start.elapsed().as_secs_f64();
+        self.metrics.finish(self.method, self.done.take(), seconds);
+    }
+}
+
+pin_project! {
+    pub struct MetricsFuture<F> {
+        #[pin]
+        future: F,
+        recording: Option<Recording>,
+    }
+}
+
+impl<F> fmt::Debug for MetricsFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricsFuture").finish()
+    }
+}
+
+impl<F, ResBody, E> Future for MetricsFuture<F>
+where
+    F: Future<Output = Result<Response<ResBody>, E>>,
+{
+    type Output = Result<Response<MetricsBody<ResBody>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result = futures_util::ready!(this.future.poll(cx));
+        let mut recording = this
+            .recording
+            .take()
+            .expect("future polled after completion");
+
+        let res = match result {
+            Ok(res) => res,
+            Err(err) => {
+                recording.done = Some((UNMATCHED_ROUTE.into(), "5xx"));
+                return Poll::Ready(Err(err));
+            }
+        };
+
+        let route = match res.extensions().get::<MatchedPath>() {
+            Some(matched_path) => matched_path.0.clone(),
+            None => UNMATCHED_ROUTE.into(),
+        };
+        recording.done = Some((route, status_label(res.status())));
+
+        Poll::Ready(Ok(res.map(|body| MetricsBody {
+            body,
+            _recording: recording,
+        })))
+    }
+}
+
+pin_project! {
+    pub struct MetricsBody<B> {
+        #[pin]
+        body: B,
+        // records the request when the body is dropped
+        _recording: Recording,
+    }
+}
+
+impl<B> fmt::Debug for MetricsBody<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricsBody").finish()
+    }
+}
+
+impl<B> http_body::Body for MetricsBody<B>
+where
+    B: http_body::Body,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        self.project().body.poll_data(cx)
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        self.project().body.poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.body.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Body;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        handler::{get, post},
+        Router,
+    };
+
+    // the sum of the latencies isn't known in advance
+    fn mask_sums(rendered: &str) -> String {
+        rendered
+            .lines()
+            .map(|line| match line.rsplit_once(' ') {
+                Some((series, _)) if series.starts_with("http_request_duration_seconds_sum") => {
+                    format!("{} <sum>\n", series)
+                }
+                _ => format!("{}\n", line),
+            })
+            .collect()
+    }
+
+    async fn send<S>(app: S, method: Method, uri: &str)
+    where
+        S: Service<Request<Body>, Response = Response<MetricsBody<crate::body::BoxBody>>>,
+        S::Error: fmt::Debug,
+    {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        hyper::body::to_bytes(res.into_body()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn renders_requests() {
+        let metrics = Metrics::with_buckets(vec![60.0, 30.0]);
+        let app = Router::new()
+            .route("/users/:id", get(|| async { "user" }))
+            .route("/fail", post(|| async { StatusCode::SERVICE_UNAVAILABLE }))
+            .layer(metrics.layer());
+
+        send(app.clone(), Method::GET, "/users/1").await;
+        send(app.clone(), Method::GET, "/users/2").await;
+        send(app.clone(), Method::GET, "/missing").await;
+        send(app, Method::POST, "/fail").await;
+
+        assert_eq!(
+            mask_sums(&metrics.render()),
+            r#"# HELP http_requests_total Total number of HTTP requests.
+# TYPE http_requests_total counter
+http_requests_total{method="GET",route="/users/:id",status="2xx"} 2
+http_requests_total{method="GET",route="unmatched",status="4xx"} 1
+http_requests_total{method="POST",route="/fail",status="5xx"} 1
+# HELP http_request_duration_seconds HTTP request latency in seconds.
+# TYPE http_request_duration_seconds histogram
+http_request_duration_seconds_bucket{method="GET",route="/users/:id",status="2xx",le="30"} 2
+http_request_duration_seconds_bucket{method="GET",route="/users/:id",status="2xx",le="60"} 2
+http_request_duration_seconds_bucket{method="GET",route="/users/:id",status="2xx",le="+Inf"} 2
+http_request_duration_seconds_sum{method="GET",route="/users/:id",status="2xx"} <sum>
+http_request_duration_seconds_count{method="GET",route="/users/:id",status="2xx"} 2
+http_request_duration_seconds_bucket{method="GET",route="unmatched",status="4xx",le="30"} 1
+http_request_duration_seconds_bucket{method="GET",route="unmatched",status="4xx",le="60"} 1
+http_request_duration_seconds_bucket{method="GET",route="unmatched",status="4xx",le="+Inf"} 1
+http_request_duration_seconds_sum{method="GET",route="unmatched",status="4xx"} <sum>
+http_request_duration_seconds_count{method="GET",route="unmatched",status="4xx"} 1
+http_request_duration_seconds_bucket{method="POST",route="/fail",status="5xx",le="30"} 1
+http_request_duration_seconds_bucket{method="POST",route="/fail",status="5xx",le="60"} 1
+http_request_duration_seconds_bucket{method="POST",route="/fail",status="5xx",le="+Inf"} 1
+http_request_duration_seconds_sum{method="POST",route="/fail",status="5xx"} <sum>
+http_request_duration_seconds_count{method="POST",route="/fail",status="5xx"} 1
+# HELP http_requests_in_flight Number of HTTP requests being served.
+# TYPE http_requests_in_flight gauge
+http_requests_in_flight{method="GET"} 0
+http_requests_in_flight{method="POST"} 0
+"#
+        );
+    }
+
+    #[test]
+    fn cumulative_buckets() {
+        let metrics = Metrics::with_buckets(vec![0.1, 1.0, 0.5]);
+        let done = || Some((Arc::from("/"), "2xx"));
+        for seconds in [0.05, 0.3, 0.4, 0.7, 2.0] {
+            metrics.start("GET");
+            metrics.finish("GET", done(), seconds);
+        }
+
+        let rendered = metrics.render();
+        let buckets = rendered
+            .lines()
+            .filter(|line| line.starts_with("http_request_duration_seconds_bucket"))
+            .map(|line| {
+                let (_, le) = line.split_once("le=").unwrap();
+                le
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            buckets,
+            ["\"0.1\"} 1", "\"0.5\"} 3", "\"1\"} 4", "\"+Inf\"} 5"]
+        );
+        assert!(rendered.contains(
+            "http_request_duration_seconds_sum{method=\"GET\",route=\"/\",status=\"2xx\"} 3.45\n"
+        ));
+    }
+
+    #[tokio::test]
+    async fn in_flight_until_body_dropped() {
+        let metrics = Metrics::new();
+        let app = Router::new()
+            .route("/", get(|| async { "hello" }))
+            .layer(metrics.layer());
+        let in_flight = |metrics: &Metrics| {
+            metrics
+                .render()
+                .lines()
+                .find_map(|line| line.strip_prefix("http_requests_in_flight{method=\"GET\"} "))
+                .map(str::to_owned)
+        };
+
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(in_flight(&metrics).as_deref(), Some("1"));
+        assert!(!metrics.render().contains("http_requests_total{"));
+
+        drop(res);
+        assert_eq!(in_flight(&metrics).as_deref(), Some("0"));
+        assert!(metrics
+            .render()
+            .contains("http_requests_total{method=\"GET\",route=\"/\",status=\"2xx\"} 1\n"));
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(
+            method_label(&Method::from_bytes(b"PURGE").unwrap()),
+            "OTHER"
+        );
+        assert_eq!(status_label(StatusCode::SWITCHING_PROTOCOLS), "1xx");
+        assert_eq!(status_label(StatusCode::NOT_MODIFIED), "3xx");
+
+        let labels = Labels {
+            method: "GET",
+            route: Arc::from("/a\"b\\c"),
+            status: "2xx",
+        };
+        assert_eq!(
+            labels.to_string(),
+            r#"method="GET",route="/a\"b\\c",status="2xx""#
+        );
+    }
+}
@@ -0,0 +1,803 @@
+// Request tracing.
+//
+// `TraceLayer` opens a `request` span for every request with the method,
+// uri and version, and records the matched route, status and latency once
+// the response is ready. An event is logged for every response at a level
+// depending on its status, and another one when the response body has been
+// sent, with the number of bytes sent and the total time.
+//
+// Both bodies are counted. `request_size` is the number of bytes of the
+// request body the inner service read, recorded when it reaches the end of
+// the body or drops it. `response_size` is recorded once the response body
+// has been sent, has failed or was dropped.
+//
+// The span is entered while the inner service runs, so events logged by
+// handlers are part of it.
+
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use bytes::{Buf, Bytes};
+use http::{HeaderMap, Request, Response, StatusCode};
+use pin_project_lite::pin_project;
+use tower_layer::Layer;
+use tower_service::Service;
+use tracing::{field, Level, Span};
+
+use crate::extract::request_parts::MatchedPath;
+
+// `tracing::event!` with a level that is only known at runtime
+macro_rules! event {
+    ($level:expr, $($tt:tt)*) => {{
+        let level = $level;
+        if level == Level::ERROR {
+            tracing::error!($($tt)*);
+        } else if level == Level::WARN {
+            tracing::warn!($($tt)*);
+        } else if level == Level::INFO {
+            tracing::info!($($tt)*);
+        } else if level == Level::DEBUG {
+            tracing::debug!($($tt)*);
+        } else {
+            tracing::trace!($($tt)*);
+        }
+    }};
+}
+
+#[derive(Clone)]
+pub struct TraceLayer {
+    config: TraceConfig,
+}
+
+#[derive(Clone)]
+struct TraceConfig {
+    success_level: Level,
+    client_error_level: Level,
+    server_error_level: Level,
+    on_finished: Option<OnFinished>,
+}
+
+type OnFinished = Arc<dyn Fn(&Finished, &Span) + Send + Sync>;
+
+impl TraceLayer {
+    // logs responses at `INFO`, client errors at `WARN` and server errors at
+    // `ERROR`
+    pub fn new() -> Self {
+        Self {
+            config: TraceConfig {
+                success_level: Level::INFO,
+                client_error_level: Level::WARN,
+                server_error_level: Level::ERROR,
+                on_finished: None,
+            },
+        }
+    }
+
+    // level for responses that aren't 4xx or 5xx
+    pub fn success_level(mut self, level: Level) -> Self {
+        self.config.success_level = level;
+        self
+    }
+
+    // level for 4xx responses
+    pub fn client_error_level(mut self, level: Level) -> Self {
+        self.config.client_error_level = level;
+        self
+    }
+
+    // level for 5xx responses and errors of the inner service
+    pub fn server_error_level(mut self, level: Level) -> Self {
+        self.config.server_error_level = level;
+        self
+    }
+
+    // Called in the span of the request once the response body has been
+    // sent, has failed or was dropped because the client went away.
+    pub fn on_finished<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Finished, &Span) + Send + Sync + 'static,
+    {
+        self.config.on_finished = Some(Arc::new(f));
+        self
+    }
+}
+
+impl Default for TraceLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for TraceLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TraceLayer")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl fmt::Debug for TraceConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TraceConfig")
+            .field("success_level", &self.success_level)
+            .field("client_error_level", &self.client_error_level)
+            .field("server_error_level", &self.server_error_level)
+            .field("on_finished", &self.on_finished.is_some())
+            .finish()
+    }
+}
+
+impl<S> Layer<S> for TraceLayer {
+    type Service = Trace<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Trace {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Trace<S> {
+    inner: S,
+    config: TraceConfig,
+}
+
+impl<S> fmt::Debug for Trace<S>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Trace")
+            .field("inner", &self.inner)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Trace<S>
+where
+    S: Service<Request<TraceRequestBody<ReqBody>>, Response = Response<ResBody>>,
+    ReqBody: http_body::Body,
+    S::Error: fmt::Display,
+    ResBody: http_body::Body,
+    ResBody::Error: fmt::Display,
+{
+    type Response = Response<TraceBody<ResBody>>;
+    type Error = S::Error;
+    type Future = TraceFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let span = tracing::info_span!(
+            "request",
+            method = %req.method(),
+            uri = %req.uri(),
+            version = ?req.version(),
+            request_size = field::Empty,
+            route = field::Empty,
+            status = field::Empty,
+            latency = field::Empty,
+            response_size = field::Empty,
+        );
+        let req = req.map(|body| TraceRequestBody {
+            body,
+            size: 0,
+            span: Some(span.clone()),
+        });
+
+        // the latency includes the time the inner service spends in `call`
+        let start = Instant::now();
+        let future = {
+            let _guard = span.enter();
+            self.inner.call(req)
+        };
+
+        TraceFuture {
+            future,
+            span,
+            config: Some(self.config.clone()),
+            start,
+        }
+    }
+}
+
+pin_project! {
+    pub struct TraceFuture<F> {
+        #[pin]
+        future: F,
+        span: Span,
+        config: Option<TraceConfig>,
+        start: Instant,
+    }
+}
+
+impl<F> fmt::Debug for TraceFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TraceFuture").finish()
+    }
+}
+
+impl<F, ResBody, E> Future for TraceFuture<F>
+where
+    F: Future<Output = Result<Response<ResBody>, E>>,
+    E: fmt::Display,
+    ResBody: http_body::Body,
+{
+    type Output = Result<Response<TraceBody<ResBody>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let _guard = this.span.enter();
+        let result = futures_util::ready!(this.future.poll(cx));
+
+        let latency = this.start.elapsed();
+        let config = this.config.take().expect("future polled after completion");
+        this.span.record("latency", field::debug(latency));
+
+        let res = match result {
+            Ok(res) => res,
+            Err(err) => {
+                event!(config.server_error_level, %err, ?latency, "request failed");
+                return Poll::Ready(Err(err));
+            }
+        };
+
+        let status = res.status();
+        let route = res.extensions().get::<MatchedPath>().cloned();
+        this.span.record("status", status.as_u16());
+        if let Some(route) = &route {
+            this.span.record("route", route.as_str());
+        }
+        event!(
+            config.level(status),
+            status = status.as_u16(),
+            ?latency,
+            "response"
+        );
+
+        let res = res.map(|body| {
+            let mut finished = Some(Finished {
+                status,
+                route,
+                latency,
+                response_size: 0,
+            });
+            // empty bodies are never polled
+            if body.is_end_stream() {
+                finish(
+                    &mut finished,
+                    &config,
+                    this.span,
+                    *this.start,
+                    Outcome::Sent,
+                );
+            }
+
+            TraceBody {
+                body,
+                finished,
+                config,
+                span: this.span.clone(),
+                start: *this.start,
+            }
+        });
+        Poll::Ready(Ok(res))
+    }
+}
+
+// Summary of a request whose response body has been sent, passed to
+// `TraceLayer::on_finished`.
+#[derive(Debug, Clone)]
+pub struct Finished {
+    status: StatusCode,
+    route: Option<MatchedPath>,
+    latency: Duration,
+    response_size: u64,
+}
+
+impl Finished {
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    // `None` when no route matched
+    pub fn route(&self) -> Option<&str> {
+        self.route.as_ref().map(MatchedPath::as_str)
+    }
+
+    // time from the request until the end of the response body
+    pub fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    // bytes of the response body that were sent
+    pub fn response_size(&self) -> u64 {
+        self.response_size
+    }
+}
+
+pin_project! {
+    pub struct TraceBody<B> {
+        #[pin]
+        body: B,
+        // taken once the body is done
+        finished: Option<Finished>,
+        config: TraceConfig,
+        span: Span,
+        start: Instant,
+    }
+
+    impl<B> PinnedDrop for TraceBody<B> {
+        // the connection was closed before the whole body was sent
+        fn drop(this: Pin<&mut Self>) {
+            let this = this.project();
+            finish(this.finished, this.config, this.span, *this.start, Outcome::Aborted);
+        }
+    }
+}
+
+impl<B> fmt::Debug for TraceBody<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TraceBody").finish()
+    }
+}
+
+impl<B> http_body::Body for TraceBody<B>
+where
+    B: http_body::Body,
+    B::Error: fmt::Display,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let mut this = self.project();
+        let _guard = this.span.enter();
+        let result = futures_util::ready!(this.body.as_mut().poll_data(cx));
+
+        match &result {
+            Some(Ok(data)) => {
+                if let Some(finished) = this.finished.as_mut() {
+                    finished.response_size += data.remaining() as u64;
+                }
+                if this.body.is_end_stream() {
+                    finish(
+                        this.finished,
+                        this.config,
+                        this.span,
+                        *this.start,
+                        Outcome::Sent,
+                    );
+                }
+            }
+            Some(Err(err)) => finish(
+                this.finished,
+                this.config,
+                this.span,
+                *this.start,
+                Outcome::Failed(err),
+            ),
+            None => finish(
+                this.finished,
+                this.config,
+                this.span,
+                *this.start,
+                Outcome::Sent,
+            ),
+        }
+
+        Poll::Ready(result)
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        self.project().body.poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.body.size_hint()
+    }
+}
+
+enum Outcome<'a> {
+    Sent,
+    Failed(&'a dyn fmt::Display),
+    Aborted,
+}
+
+pin_project! {
+    // The request body as seen by the inner service, counting the bytes it
+    // reads.
+    pub struct TraceRequestBody<B> {
+        #[pin]
+        body: B,
+        size: u64,
+        // taken once `request_size` is recorded
+        span: Option<Span>,
+    }
+
+    impl<B> PinnedDrop for TraceRequestBody<B> {
+        // the service is done with the body, whether it read all of it or not
+        fn drop(this: Pin<&mut Self>) {
+            let this = this.project();
+            record_request_size(this.span, *this.size);
+        }
+    }
+}
+
+impl<B> fmt::Debug for TraceRequestBody<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TraceRequestBody")
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+// Extractors that buffer the body put it back through `From<Bytes>`, the
+// original body has already recorded its size by then.
+impl<B> From<Bytes> for TraceRequestBody<B>
+where
+    B: From<Bytes>,
+{
+    fn from(bytes: Bytes) -> Self {
+        Self {
+            body: B::from(bytes),
+            size: 0,
+            span: None,
+        }
+    }
+}
+
+impl<B> http_body::Body for TraceRequestBody<B>
+where
+    B: http_body::Body,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let mut this = self.project();
+        let result = futures_util::ready!(this.body.as_mut().poll_data(cx));
+
+        match &result {
+            Some(Ok(data)) => {
+                *this.size += data.remaining() as u64;
+                if this.body.is_end_stream() {
+                    record_request_size(this.span, *this.size);
+                }
+            }
+            Some(Err(_)) => {}
+            None => record_request_size(this.span, *this.size),
+        }
+
+        Poll::Ready(result)
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        self.project().body.poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.body.size_hint()
+    }
+}
+
+fn record_request_size(span: &mut Option<Span>, size: u64) {
+    if let Some(span) = span.take() {
+        span.record("request_size", size);
+    }
+}
+
+fn finish(
+    finished: &mut Option<Finished>,
+    config: &TraceConfig,
+    span: &Span,
+    start: Instant,
+    outcome: Outcome<'_>,
+) {
+    let mut finished = match finished.take() {
+        Some(finished) => finished,
+        None => return,
+    };
+    finished.latency = start.elapsed();
+
+    let response_size = finished.response_size;
+    let latency = finished.latency;
+    span.record("response_size", response_size);
+    match outcome {
+        Outcome::Sent => {
+            tracing::debug!(response_size, ?latency, "finished sending response body")
+        }
+        Outcome::Failed(err) => event!(
+            config.server_error_level,
+            %err, response_size, ?latency, "response body failed"
+        ),
+        Outcome::Aborted => {
+            tracing::debug!(
+                response_size,
+                ?latency,
+                "response body dropped before it was sent"
+            )
+        }
+    }
+
+    if let Some(on_finished) = &config.on_finished {
+        on_finished(&finished, span);
+    }
+}
+
+impl TraceConfig {
+    fn level(&self, status: StatusCode) -> Level {
+        if status.is_server_error() {
+            self.server_error_level
+        } else if status.is_client_error() {
+            self.client_error_level
+        } else {
+            self.success_level
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        convert::Infallible,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+    };
+
+    use futures_util::stream;
+    use http_body::Body as _;
+    use hyper::Body;
+    use tower::{service_fn, ServiceExt};
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Subscriber,
+    };
+    use tracing_subscriber::{
+        layer::{Context as LayerContext, SubscriberExt},
+        registry::LookupSpan,
+        Registry,
+    };
+
+    use super::*;
+    use crate::{
+        handler::{get, post},
+        Router,
+    };
+
+    // Events and the fields of the `request` span, captured with a
+    // `tracing_subscriber` layer.
+    #[derive(Clone, Default)]
+    struct Capture {
+        events: Arc<Mutex<Vec<(Level, String)>>>,
+        span: Arc<Mutex<HashMap<String, String>>>,
+    }
+
+    struct Fields<'a>(&'a mut HashMap<String, String>);
+
+    impl Visit for Fields<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_owned(), value.to_owned());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0
+                .insert(field.name().to_owned(), format!("{:?}", value));
+        }
+    }
+
+    impl<S> tracing_subscriber::Layer<S> for Capture
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_new_span(&self, attrs: &span::Attributes<'_>, _: &span::Id, _: LayerContext<'_, S>) {
+            attrs.record(&mut Fields(&mut self.span.lock().unwrap()));
+        }
+
+        fn on_record(&self, _: &span::Id, values: &span::Record<'_>, _: LayerContext<'_, S>) {
+            values.record(&mut Fields(&mut self.span.lock().unwrap()));
+        }
+
+        fn on_event(&self, event: &Event<'_>, _: LayerContext<'_, S>) {
+            let mut fields = HashMap::new();
+            event.record(&mut Fields(&mut fields));
+            let message = fields.remove("message").unwrap_or_default();
+            self.events
+                .lock()
+                .unwrap()
+                .push((*event.metadata().level(), message));
+        }
+    }
+
+    impl Capture {
+        fn install(&self) -> tracing::subscriber::DefaultGuard {
+            tracing::subscriber::set_default(Registry::default().with(self.clone()))
+        }
+
+        fn level_of(&self, message: &str) -> Option<Level> {
+            self.events
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|(_, msg)| msg == message)
+                .map(|(level, _)| *level)
+        }
+
+        fn span_field(&self, name: &str) -> Option<String> {
+            self.span.lock().unwrap().get(name).cloned()
+        }
+    }
+
+    fn request(uri: &str) -> Request<Body> {
+        Request::builder().uri(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn level_depends_on_status() {
+        let app = Router::new()
+            .route("/ok", get(|| async { "ok" }))
+            .route("/fail", get(|| async { StatusCode::INTERNAL_SERVER_ERROR }))
+            .layer(TraceLayer::new());
+
+        for (uri, level) in [
+            ("/ok", Level::INFO),
+            ("/missing", Level::WARN),
+            ("/fail", Level::ERROR),
+        ] {
+            let capture = Capture::default();
+            let _guard = capture.install();
+
+            let res = app.clone().oneshot(request(uri)).await.unwrap();
+            hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert_eq!(capture.level_of("response"), Some(level), "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn records_route_and_sizes() {
+        let capture = Capture::default();
+        let _guard = capture.install();
+
+        let app = Router::new()
+            .route("/users/:id", post(|body: String| async move { body + "!" }))
+            .layer(TraceLayer::new());
+
+        // chunked, the size can only come from counting the body
+        let chunks = stream::iter([Ok::<_, Infallible>("hel"), Ok("lo")]);
+        let req = Request::builder()
+            .method("POST")
+            .uri("/users/42")
+            .body(Body::wrap_stream(chunks))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(capture.span_field("request_size").as_deref(), Some("5"));
+        assert_eq!(capture.span_field("route").as_deref(), Some("/users/:id"));
+        assert_eq!(capture.span_field("status").as_deref(), Some("200"));
+        assert_eq!(capture.span_field("response_size"), None);
+
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body, "hello!");
+        assert_eq!(capture.span_field("response_size").as_deref(), Some("6"));
+    }
+
+    #[tokio::test]
+    async fn unread_request_body() {
+        let capture = Capture::default();
+        let _guard = capture.install();
+
+        let app = Router::new()
+            .route("/", post(|| async {}))
+            .layer(TraceLayer::new());
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            .body(Body::from("ignored"))
+            .unwrap();
+        app.oneshot(req).await.unwrap();
+        assert_eq!(capture.span_field("request_size").as_deref(), Some("0"));
+    }
+
+    // a service answering with `body`, counting the calls to `on_finished`
+    fn counted<B>(
+        body: fn() -> B,
+    ) -> (
+        impl Service<Request<Body>, Response = Response<TraceBody<B>>, Error = Infallible>,
+        Arc<AtomicUsize>,
+    )
+    where
+        B: http_body::Body,
+        B::Error: fmt::Display,
+    {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let layer = TraceLayer::new().on_finished({
+            let calls = calls.clone();
+            move |_, _| {
+                calls.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        let svc = service_fn(move |_: Request<TraceRequestBody<Body>>| async move {
+            Ok::<_, Infallible>(Response::new(body()))
+        });
+        (layer.layer(svc), calls)
+    }
+
+    #[tokio::test]
+    async fn on_finished_when_sent() {
+        let (svc, calls) = counted(|| Body::from("hello"));
+
+        let res = svc.oneshot(request("/")).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn on_finished_when_empty() {
+        let (svc, calls) = counted(Body::empty);
+
+        let res = svc.oneshot(request("/")).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        drop(res);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn on_finished_when_failed() {
+        let (svc, calls) = counted(|| {
+            Body::wrap_stream(stream::iter([
+                Ok("partial"),
+                Err(std::io::Error::other("broken")),
+            ]))
+        });
+
+        let res = svc.oneshot(request("/")).await.unwrap();
+        assert!(hyper::body::to_bytes(res.into_body()).await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn on_finished_when_dropped() {
+        let (svc, calls) =
+            counted(|| Body::wrap_stream(stream::iter([Ok::<_, Infallible>("one"), Ok("two")])));
+
+        let mut body = svc.oneshot(request("/")).await.unwrap().into_body();
+        body.data().await.unwrap().unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        drop(body);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,3 +1,5 @@
+pub mod sse;
+
 use std::{borrow::Cow, convert::Infallible};
 
 use bytes::Bytes;
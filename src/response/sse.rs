@@ -0,0 +1,441 @@
+// Server-Sent Events.
+//
+// `Sse` turns a stream of `Event`s into a `text/event-stream` response, each
+// event is sent as soon as the stream yields it. With a `KeepAlive` a
+// comment is sent whenever no event was sent for a while, so proxies don't
+// close idle connections.
+
+use std::{
+    fmt::{self, Write},
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use futures_util::stream::Stream;
+use http::{header, HeaderMap, HeaderValue, Response};
+use pin_project_lite::pin_project;
+use serde::Serialize;
+use sync_wrapper::SyncWrapper;
+use tokio::time::{sleep, Instant, Sleep};
+
+use super::IntoResponse;
+use crate::BoxError;
+
+#[derive(Clone)]
+pub struct Sse<S> {
+    stream: S,
+    keep_alive: Option<KeepAlive>,
+}
+
+impl<S> Sse<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            keep_alive: None,
+        }
+    }
+
+    pub fn keep_alive(mut self, keep_alive: KeepAlive) -> Self {
+        self.keep_alive = Some(keep_alive);
+        self
+    }
+}
+
+impl<S> fmt::Debug for Sse<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sse")
+            .field("stream", &std::any::type_name::<S>())
+            .field("keep_alive", &self.keep_alive)
+            .finish()
+    }
+}
+
+impl<S, E> IntoResponse for Sse<S>
+where
+    S: Stream<Item = Result<Event, E>> + Send + 'static,
+    E: Into<BoxError>,
+{
+    type Body = SseBody<S>;
+    type BodyError = E;
+
+    fn into_response(self) -> Response<Self::Body> {
+        let body = SseBody {
+            stream: SyncWrapper::new(self.stream),
+            keep_alive: self.keep_alive.map(KeepAliveTimer::new),
+        };
+
+        let mut res = Response::new(body);
+        let headers = res.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/event-stream"),
+        );
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        res
+    }
+}
+
+pin_project! {
+    pub struct SseBody<S> {
+        #[pin]
+        stream: SyncWrapper<S>,
+        keep_alive: Option<KeepAliveTimer>,
+    }
+}
+
+impl<S> fmt::Debug for SseBody<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SseBody").finish()
+    }
+}
+
+impl<S, E> http_body::Body for SseBody<S>
+where
+    S: Stream<Item = Result<Event, E>>,
+{
+    type Data = Bytes;
+    type Error = E;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = self.project();
+
+        match this.stream.get_pin_mut().poll_next(cx) {
+            Poll::Ready(Some(Ok(event))) => {
+                if let Some(keep_alive) = this.keep_alive {
+                    keep_alive.reset();
+                }
+                Poll::Ready(Some(Ok(event.finalize())))
+            }
+            Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(err))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => match this.keep_alive {
+                Some(keep_alive) => keep_alive.poll_event(cx).map(|event| Some(Ok(event))),
+                None => Poll::Pending,
+            },
+        }
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Poll::Ready(Ok(None))
+    }
+}
+
+// A single event, built up with the methods below. Lines of `data` and
+// `comment` are sent as separate fields so multi-line values arrive intact.
+#[derive(Debug, Default, Clone)]
+pub struct Event {
+    data: Option<String>,
+    event: Option<String>,
+    id: Option<String>,
+    retry: Option<Duration>,
+    comment: Option<String>,
+}
+
+impl Event {
+    pub fn data(mut self, data: impl Into<String>) -> Self {
+        self.data = Some(data.into());
+        self
+    }
+
+    // serialize `data` as JSON, which is always a single line
+    pub fn json_data<T>(mut self, data: T) -> Result<Self, serde_json::Error>
+    where
+        T: Serialize,
+    {
+        self.data = Some(serde_json::to_string(&data)?);
+        Ok(self)
+    }
+
+    // Panics if the name contains a line break.
+    pub fn event(mut self, event: impl Into<String>) -> Self {
+        let event = event.into();
+        assert!(
+            !contains_line_break(&event),
+            "SSE event name cannot contain line breaks"
+        );
+        self.event = Some(event);
+        self
+    }
+
+    // Panics if the id contains a line break or a null character.
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        let id = id.into();
+        assert!(
+            !contains_line_break(&id) && !id.contains('\0'),
+            "SSE id cannot contain line breaks or null characters"
+        );
+        self.id = Some(id);
+        self
+    }
+
+    // how long the client waits before reconnecting
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    // a comment is ignored by the client
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    fn finalize(&self) -> Bytes {
+        let mut buf = String::new();
+
+        if let Some(comment) = &self.comment {
+            for line in lines(comment) {
+                field(&mut buf, "", line);
+            }
+        }
+        if let Some(event) = &self.event {
+            field(&mut buf, "event", event);
+        }
+        if let Some(data) = &self.data {
+            for line in lines(data) {
+                field(&mut buf, "data", line);
+            }
+        }
+        if let Some(id) = &self.id {
+            field(&mut buf, "id", id);
+        }
+        if let Some(retry) = self.retry {
+            let _ = writeln!(buf, "retry: {}", retry.as_millis());
+        }
+
+        // an empty line dispatches the event
+        buf.push('\n');
+        Bytes::from(buf)
+    }
+}
+
+fn field(buf: &mut String, name: &str, value: &str) {
+    buf.push_str(name);
+    buf.push(':');
+    // a leading space would be removed by the client, the one we add is
+    // removed instead
+    buf.push(' ');
+    buf.push_str(value);
+    buf.push('\n');
+}
+
+// split on `\r\n`, `\n` and `\r`, the line endings the client accepts
+fn lines(value: &str) -> impl Iterator<Item = &str> {
+    let mut rest = Some(value);
+    std::iter::from_fn(move || {
+        let value = rest?;
+        match value.find(['\r', '\n']) {
+            Some(end) => {
+                let next = if value[end..].starts_with("\r\n") {
+                    end + 2
+                } else {
+                    end + 1
+                };
+                rest = Some(&value[next..]);
+                Some(&value[..end])
+            }
+            None => {
+                rest = None;
+                Some(value)
+            }
+        }
+    })
+}
+
+fn contains_line_break(value: &str) -> bool {
+    value.contains(['\r', '\n'])
+}
+
+// Send an event after `interval` without any other event, by default an
+// empty comment every 15 seconds.
+#[derive(Debug, Clone)]
+pub struct KeepAlive {
+    event: Bytes,
+    interval: Duration,
+}
+
+impl KeepAlive {
+    pub fn new() -> Self {
+        Self {
+            event: Bytes::from_static(b":\n\n"),
+            interval: Duration::from_secs(15),
+        }
+    }
+
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    // send a comment with this text
+    pub fn text(self, text: impl Into<String>) -> Self {
+        self.event(Event::default().comment(text))
+    }
+
+    // send this event instead of a comment
+    pub fn event(mut self, event: Event) -> Self {
+        self.event = event.finalize();
+        self
+    }
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct KeepAliveTimer {
+    keep_alive: KeepAlive,
+    sleep: Pin<Box<Sleep>>,
+}
+
+impl KeepAliveTimer {
+    fn new(keep_alive: KeepAlive) -> Self {
+        let sleep = Box::pin(sleep(keep_alive.interval));
+        Self { keep_alive, sleep }
+    }
+
+    fn reset(&mut self) {
+        self.sleep
+            .as_mut()
+            .reset(Instant::now() + self.keep_alive.interval);
+    }
+
+    fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<Bytes> {
+        futures_util::ready!(self.sleep.as_mut().poll(cx));
+        self.reset();
+        Poll::Ready(self.keep_alive.event.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use futures_util::stream;
+    use http_body::Body as _;
+
+    use super::*;
+
+    fn finalize(event: Event) -> String {
+        String::from_utf8(event.finalize().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn multi_line_data() {
+        let event = Event::default().data("a\nb\r\nc");
+        assert_eq!(finalize(event), "data: a\ndata: b\ndata: c\n\n");
+
+        let event = Event::default().data("a\rb\n").comment("one\ntwo");
+        assert_eq!(
+            finalize(event),
+            ": one\n: two\ndata: a\ndata: b\ndata: \n\n"
+        );
+    }
+
+    #[test]
+    fn all_fields() {
+        let event = Event::default()
+            .event("update")
+            .id("42")
+            .retry(Duration::from_secs(3))
+            .json_data(serde_json::json!({ "n": 1 }))
+            .unwrap();
+        assert_eq!(
+            finalize(event),
+            "event: update\ndata: {\"n\":1}\nid: 42\nretry: 3000\n\n"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "SSE event name cannot contain line breaks")]
+    fn event_name_with_line_break() {
+        let _ = Event::default().event("a\nb");
+    }
+
+    #[test]
+    fn headers() {
+        let res = Sse::new(stream::empty::<Result<Event, Infallible>>()).into_response();
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "text/event-stream");
+        assert_eq!(res.headers()[header::CACHE_CONTROL], "no-cache");
+    }
+
+    // every chunk of the body with the time since the start it was sent at
+    async fn chunks<S>(sse: Sse<S>) -> Vec<(String, Duration)>
+    where
+        S: Stream<Item = Result<Event, Infallible>> + Send + 'static,
+    {
+        let start = Instant::now();
+        let mut body = Box::pin(sse.into_response().into_body());
+        let mut chunks = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = String::from_utf8(chunk.unwrap().to_vec()).unwrap();
+            chunks.push((chunk, start.elapsed()));
+        }
+        chunks
+    }
+
+    // `data: 1` right away and `data: 2` after `delay`
+    fn delayed(delay: Duration) -> impl Stream<Item = Result<Event, Infallible>> {
+        stream::unfold(0, move |n| async move {
+            match n {
+                0 => {}
+                1 => sleep(delay).await,
+                _ => return None,
+            }
+            Some((Ok(Event::default().data((n + 1).to_string())), n + 1))
+        })
+    }
+
+    #[tokio::test]
+    async fn keep_alive_while_idle() {
+        let keep_alive = KeepAlive::new()
+            .interval(Duration::from_millis(100))
+            .text("ping");
+        let chunks =
+            chunks(Sse::new(delayed(Duration::from_millis(350))).keep_alive(keep_alive)).await;
+
+        let texts = chunks
+            .iter()
+            .map(|(chunk, _)| chunk.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            [
+                "data: 1\n\n",
+                ": ping\n\n",
+                ": ping\n\n",
+                ": ping\n\n",
+                "data: 2\n\n"
+            ]
+        );
+
+        for (i, (_, at)) in chunks[1..4].iter().enumerate() {
+            let expected = Duration::from_millis(100 * (i as u64 + 1));
+            assert!(*at >= expected, "keep-alive {} sent after {:?}", i, at);
+        }
+    }
+
+    #[tokio::test]
+    async fn no_keep_alive_while_busy() {
+        let keep_alive = KeepAlive::new().interval(Duration::from_millis(200));
+        let chunks =
+            chunks(Sse::new(delayed(Duration::from_millis(50))).keep_alive(keep_alive)).await;
+
+        let texts = chunks
+            .iter()
+            .map(|(chunk, _)| chunk.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(texts, ["data: 1\n\n", "data: 2\n\n"]);
+    }
+}
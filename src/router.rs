@@ -7,6 +7,10 @@ pub mod empty_router;
 pub mod future;
 pub mod method_filter;
 pub mod route;
+mod tree;
+
+#[doc(hidden)]
+pub use self::tree::PathTree;
 
 use std::{
     borrow::Cow,
@@ -22,11 +26,17 @@ use tower::util::ServiceExt;
 use tower_service::Service;
 
 use self::{
-    empty_router::{EmptyRouter, FromEmptyRouter},
+    empty_router::{EmptyRouter, FromEmptyRouter, NoMethodMatch},
     future::EmptyRouterFuture,
-    route::{PathPattern, Route},
+    method_filter::MethodFilter,
+    route::{RouteConflict, Routes},
+};
+use crate::{
+    body::BoxBody,
+    extract::builtin::connect_info::IntoMakeServiceWithConnectInfo,
+    handler::{Handler, IntoService, OnMethod},
+    service::HandleError,
 };
-use crate::{body::BoxBody, service::HandleError};
 
 #[derive(Debug, Clone)]
 pub struct Router<S> {
@@ -34,29 +44,165 @@ pub struct Router<S> {
     svc: S,
 }
 
-// `EmptyRouter` is one kind of router
-// Make `Router` have the method of `EmptyRouter` via generic constraints
-impl<E> Router<EmptyRouter<E>> {
+// Routes are added to the route table at the bottom of the router, layers
+// wrap the whole router
+impl<B, E> Router<Routes<B, E>> {
     // create a new router, default is not found
     pub fn new() -> Self {
-        Self {
-            svc: EmptyRouter::not_found(),
+        Self { svc: Routes::new() }
+    }
+
+    // add a route for the methods of `method_router`, like
+    // `get(list_users).post(create_user)`
+    //
+    // Routes for the same path can be split over several calls as long as
+    // they don't handle the same method. Panics if the route conflicts with
+    // one that was added before, see `try_route` to handle the conflict
+    // instead.
+    #[track_caller]
+    pub fn route<H, T, F>(self, path: &str, method_router: OnMethod<H, B, T, F>) -> Self
+    where
+        OnMethod<H, B, T, F>: Service<Request<B>, Response = Response<BoxBody>, Error = E>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        <OnMethod<H, B, T, F> as Service<Request<B>>>::Future: Send + 'static,
+        B: Send + 'static,
+    {
+        match self.try_route(path, method_router) {
+            Ok(router) => router,
+            Err(conflict) => panic!("{}", conflict),
         }
     }
 
+    pub fn try_route<H, T, F>(
+        mut self,
+        path: &str,
+        method_router: OnMethod<H, B, T, F>,
+    ) -> Result<Self, RouteConflict>
+    where
+        OnMethod<H, B, T, F>: Service<Request<B>, Response = Response<BoxBody>, Error = E>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        <OnMethod<H, B, T, F> as Service<Request<B>>>::Future: Send + 'static,
+        B: Send + 'static,
+    {
+        let methods = method_router.all_methods;
+        self.svc.route(path, method_router, methods)?;
+        Ok(self)
+    }
+
+    // add a route calling `svc` for every method, it conflicts with any
+    // other route for the same path
+    #[track_caller]
+    pub fn route_service<T>(self, path: &str, svc: T) -> Self
+    where
+        T: Service<Request<B>, Response = Response<BoxBody>, Error = E>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        T::Future: Send + 'static,
+        B: Send + 'static,
+    {
+        match self.try_route_service(path, svc) {
+            Ok(router) => router,
+            Err(conflict) => panic!("{}", conflict),
+        }
+    }
+
+    pub fn try_route_service<T>(mut self, path: &str, svc: T) -> Result<Self, RouteConflict>
+    where
+        T: Service<Request<B>, Response = Response<BoxBody>, Error = E>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        T::Future: Send + 'static,
+        B: Send + 'static,
+    {
+        self.svc.route(path, svc, MethodFilter::all())?;
+        Ok(self)
+    }
+
+    // nest a router or service under a path prefix, the prefix is stripped
+    // from the uri before the inner service is called
     //
-}
+    // panics on conflicts like `route`, see `try_nest`
+    #[track_caller]
+    pub fn nest<T>(self, path: &str, svc: T) -> Self
+    where
+        T: Service<Request<B>, Response = Response<BoxBody>, Error = E>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        T::Future: Send + 'static,
+        B: Send + 'static,
+    {
+        match self.try_nest(path, svc) {
+            Ok(router) => router,
+            Err(conflict) => panic!("{}", conflict),
+        }
+    }
 
-impl<S> Router<S> {
-    // add one more constraint T
-    pub fn route<T>(self, path: &str, svc: T) -> Router<Route<T, S>> {
-        self.map(|fallback| Route {
-            pattern: PathPattern::new(path),
-            svc,
-            fallback,
-        })
+    pub fn try_nest<T>(mut self, path: &str, svc: T) -> Result<Self, RouteConflict>
+    where
+        T: Service<Request<B>, Response = Response<BoxBody>, Error = E>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        T::Future: Send + 'static,
+        B: Send + 'static,
+    {
+        self.svc.nest(path, svc)?;
+        Ok(self)
+    }
+
+    // service called with the original request when no route matches, see
+    // `fallback_handler` for handlers
+    pub fn fallback<T>(mut self, svc: T) -> Self
+    where
+        T: Service<Request<B>, Response = Response<BoxBody>, Error = E>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        T::Future: Send + 'static,
+        B: Send + 'static,
+    {
+        self.svc.fallback(svc);
+        self
     }
 
+    // handler called with the original request when no route matches
+    pub fn fallback_handler<H, T>(self, handler: H) -> Self
+    where
+        H: Handler<B, T>,
+        IntoService<H, B, T>: Service<Request<B>, Response = Response<BoxBody>, Error = E>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        <IntoService<H, B, T> as Service<Request<B>>>::Future: Send + 'static,
+        B: Send + 'static,
+    {
+        self.fallback(handler.into_service())
+    }
+
+    // answer `OPTIONS` requests with `204 No Content` and an `Allow` header
+    // for paths that have routes but no `options` handler
+    pub fn auto_options(mut self) -> Self {
+        self.svc.auto_options();
+        self
+    }
+}
+
+impl<S> Router<S> {
     fn map<F, S2>(self, f: F) -> Router<S2>
     where
         F: FnOnce(S) -> S2,
@@ -71,6 +217,15 @@ impl<S> Router<S> {
         IntoMakeService::new(self.svc)
     }
 
+    // like `into_make_service`, but makes `C` for each connection available
+    // to the handlers through the `ConnectInfo<C>` extractor
+    pub fn into_make_service_with_connect_info<C>(self) -> IntoMakeServiceWithConnectInfo<S, C>
+    where
+        S: Clone,
+    {
+        IntoMakeServiceWithConnectInfo::new(self.svc)
+    }
+
     pub fn layer<L>(self, layer: L) -> Router<Layered<L::Service>>
     where
         L: tower_layer::Layer<S>,
@@ -163,7 +318,7 @@ where
     }
 }
 
-impl<E> Default for Router<EmptyRouter<E>> {
+impl<B, E> Default for Router<Routes<B, E>> {
     fn default() -> Self {
         Self::new()
     }
@@ -191,6 +346,7 @@ where
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct CheckInfallible<S>(S);
 
 impl<R, S> Service<R> for CheckInfallible<S>
@@ -211,3 +367,165 @@ where
         self.0.call(req)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use http::{header, Method, StatusCode};
+    use hyper::Body;
+
+    use super::*;
+    use crate::handler::{get, on, post};
+
+    async fn call<S>(app: S, method: Method, uri: &str) -> Response<BoxBody>
+    where
+        S: Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible>,
+    {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(req).await.unwrap()
+    }
+
+    async fn body(res: Response<BoxBody>) -> String {
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn split_methods_on_one_path() {
+        let app = Router::new()
+            .route("/users/:id", get(|| async { "get" }))
+            .route("/users/:name", post(|| async { "post" }));
+
+        let res = call(app.clone(), Method::GET, "/users/1").await;
+        assert_eq!(body(res).await, "get");
+        let res = call(app.clone(), Method::POST, "/users/1").await;
+        assert_eq!(body(res).await, "post");
+
+        let res = call(app, Method::PUT, "/users/1").await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers()[header::ALLOW], "GET,HEAD,POST");
+    }
+
+    #[tokio::test]
+    async fn fallback_handler() {
+        let app = Router::new()
+            .route("/", get(|| async { "home" }))
+            .fallback_handler(|req: Request<Body>| async move {
+                (StatusCode::NOT_FOUND, format!("no route for {}", req.uri()))
+            });
+
+        let res = call(app.clone(), Method::GET, "/missing").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(body(res).await, "no route for /missing");
+
+        // a path that has routes isn't the fallback's business
+        let res = call(app, Method::POST, "/").await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn auto_options() {
+        let app = Router::new()
+            .route(
+                "/users",
+                get(|| async { "list" }).post(|| async { "create" }),
+            )
+            .route("/users/:id", on(MethodFilter::DELETE, || async {}))
+            .auto_options();
+
+        let res = call(app.clone(), Method::OPTIONS, "/users").await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(res.headers()[header::ALLOW], "GET,HEAD,POST,OPTIONS");
+        assert_eq!(body(res).await, "");
+
+        let res = call(app.clone(), Method::OPTIONS, "/users/1").await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(res.headers()[header::ALLOW], "DELETE,OPTIONS");
+
+        let res = call(app, Method::OPTIONS, "/missing").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert!(res.headers().get(header::ALLOW).is_none());
+    }
+
+    #[tokio::test]
+    async fn auto_options_leaves_options_handlers_alone() {
+        let app = Router::new()
+            .route("/", get(|| async {}).options(|| async { "custom" }))
+            .route("/a", get(|| async {}))
+            .route(
+                "/a",
+                on(MethodFilter::OPTIONS, || async { "separate route" }),
+            )
+            .auto_options();
+
+        let res = call(app.clone(), Method::OPTIONS, "/").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body(res).await, "custom");
+
+        let res = call(app, Method::OPTIONS, "/a").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body(res).await, "separate route");
+    }
+
+    #[tokio::test]
+    async fn options_without_auto_options() {
+        let app = Router::new().route("/", get(|| async {}));
+
+        let res = call(app, Method::OPTIONS, "/").await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers()[header::ALLOW], "GET,HEAD");
+    }
+
+    #[test]
+    fn overlapping_methods_conflict() {
+        let conflict = Router::<Routes<Body>>::new()
+            .route("/users/:id", get(|| async {}).post(|| async {}))
+            .try_route(
+                "/users/:name",
+                on(MethodFilter::PUT, || async {}).post(|| async {}),
+            )
+            .unwrap_err();
+
+        assert_eq!(conflict.route(), "/users/:name");
+        assert_eq!(conflict.existing(), "/users/:id");
+        assert_eq!(conflict.methods(), MethodFilter::POST);
+        assert_eq!(
+            conflict.to_string(),
+            "Route `/users/:name` conflicts with the existing route `/users/:id`, \
+             both match POST requests"
+        );
+    }
+
+    #[test]
+    fn services_conflict_with_every_method() {
+        let svc = tower::service_fn(|_: Request<Body>| async {
+            Ok::<_, Infallible>(Response::new(crate::body::empty()))
+        });
+
+        let conflict = Router::<Routes<Body>>::new()
+            .route("/", post(|| async {}))
+            .try_route_service("/", svc)
+            .unwrap_err();
+        assert_eq!(conflict.methods(), MethodFilter::POST);
+
+        let conflict = Router::<Routes<Body>>::new()
+            .route_service("/", svc)
+            .try_route("/", get(|| async {}))
+            .unwrap_err();
+        assert_eq!(conflict.methods(), MethodFilter::GET | MethodFilter::HEAD);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Route `/users/:name` conflicts with the existing route `/users/:id`, \
+                    both match GET,HEAD requests"
+    )]
+    fn route_panics_on_conflict() {
+        let _ = Router::<Routes<Body>>::new()
+            .route("/users/:id", get(|| async {}))
+            .route("/users/:name", get(|| async {}));
+    }
+}
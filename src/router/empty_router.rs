@@ -1,6 +1,8 @@
 use std::{fmt::Debug, marker::PhantomData};
 
-use super::*;
+use http::header;
+
+use super::{method_filter::AllowedMethods, *};
 
 pub struct EmptyRouter<E = Infallible> {
     // Defined by http crate
@@ -77,6 +79,15 @@ where
 
         // create an empty body
         let mut res = Response::new(crate::body::empty());
+
+        // 405 must list the methods the resource does support
+        if self.status == StatusCode::METHOD_NOT_ALLOWED {
+            if let Some(allowed) = request.extensions().get::<AllowedMethods>() {
+                res.headers_mut()
+                    .insert(header::ALLOW, allowed.0.to_allow_header());
+            }
+        }
+
         // insert request
         res.extensions_mut().insert(FromEmptyRouter { request });
         // change status
@@ -89,7 +100,7 @@ where
 }
 
 #[derive(Copy, Clone)]
-struct NoMethodMatch;
+pub(crate) struct NoMethodMatch;
 
 pub struct FromEmptyRouter<B> {
     pub request: Request<B>,
@@ -1,5 +1,5 @@
 use bitflags::bitflags;
-use http::Method;
+use http::{HeaderValue, Method};
 
 bitflags! {
      pub struct MethodFilter: u16 {
@@ -33,4 +33,43 @@ impl MethodFilter {
 
         self.contains(method)
     }
+
+    // value of the `Allow` header listing the methods in the filter
+    pub(crate) fn to_allow_header(self) -> HeaderValue {
+        const METHODS: [(MethodFilter, &str); 9] = [
+            (MethodFilter::GET, "GET"),
+            (MethodFilter::HEAD, "HEAD"),
+            (MethodFilter::POST, "POST"),
+            (MethodFilter::PUT, "PUT"),
+            (MethodFilter::DELETE, "DELETE"),
+            (MethodFilter::PATCH, "PATCH"),
+            (MethodFilter::OPTIONS, "OPTIONS"),
+            (MethodFilter::CONNECT, "CONNECT"),
+            (MethodFilter::TRACE, "TRACE"),
+        ];
+
+        let allow = METHODS
+            .iter()
+            .filter(|(method, _)| self.contains(*method))
+            .map(|(_, name)| *name)
+            .collect::<Vec<_>>()
+            .join(",");
+
+        HeaderValue::from_str(&allow).expect("method names are valid header values")
+    }
+}
+
+// The methods accepted by the routes that matched the path of a request,
+// collected by `OnMethod` so a 405 response can list them in `Allow`.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct AllowedMethods(pub(crate) MethodFilter);
+
+impl AllowedMethods {
+    pub(crate) fn add<B>(req: &mut http::Request<B>, method: MethodFilter) {
+        let allowed = req
+            .extensions()
+            .get::<AllowedMethods>()
+            .map_or(method, |allowed| allowed.0 | method);
+        req.extensions_mut().insert(AllowedMethods(allowed));
+    }
 }
@@ -1,147 +1,528 @@
-use bytes::Bytes;
-use regex::Regex;
+use futures_util::future::BoxFuture;
+use http::{header, uri::PathAndQuery, Method, Uri};
 
-use self::future::RouteFuture;
+use self::{
+    future::RoutesFuture,
+    method_filter::{AllowedMethods, MethodFilter},
+    tree::{Match, Node, Overlap},
+};
 use super::*;
-use crate::util::ByteStr;
+use percent_encoding::percent_decode_str;
 
+use crate::{
+    extract::request_parts::{MatchedPath, OriginalUri},
+    util::ByteStr,
+};
+
+// The route table of a `Router`.
+//
+// Routes are type erased and looked up through a prefix tree, so adding a
+// route doesn't change the type of the router and matching a request only
+// looks at the routes along its path, see `tree` for the cost.
+pub struct Routes<B, E = Infallible> {
+    table: Arc<Table<B, E>>,
+    // called when no route matches, 404 when not set
+    fallback: Option<Arc<dyn CloneOneshot<B, E>>>,
+    // answer `OPTIONS` for routes without an options handler
+    auto_options: bool,
+}
+
+struct Table<B, E> {
+    node: Node,
+    endpoints: Vec<Endpoint<B, E>>,
+}
+
+struct Endpoint<B, E> {
+    pattern: PathPattern,
+    svc: Arc<dyn CloneOneshot<B, E>>,
+}
+
+impl<B, E> Routes<B, E> {
+    pub(crate) fn new() -> Self {
+        Self {
+            table: Arc::new(Table {
+                node: Node::default(),
+                endpoints: Vec::new(),
+            }),
+            fallback: None,
+            auto_options: false,
+        }
+    }
+
+    pub(crate) fn auto_options(&mut self) {
+        self.auto_options = true;
+    }
+
+    pub(crate) fn fallback<T>(&mut self, svc: T)
+    where
+        T: Service<Request<B>, Response = Response<BoxBody>, Error = E>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        T::Future: Send + 'static,
+        B: Send + 'static,
+    {
+        self.fallback = Some(Arc::new(svc));
+    }
+
+    pub(crate) fn route<T>(
+        &mut self,
+        path: &str,
+        svc: T,
+        methods: MethodFilter,
+    ) -> Result<(), RouteConflict>
+    where
+        T: Service<Request<B>, Response = Response<BoxBody>, Error = E>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        T::Future: Send + 'static,
+        B: Send + 'static,
+    {
+        let pattern = PathPattern::new(path);
+        let table = self.table_mut();
+        let id = table.endpoints.len();
+        table
+            .node
+            .insert_route(pattern.segments(), id, methods)
+            .map_err(|overlap| table.conflict(&pattern, overlap))?;
+        table.push(pattern, svc);
+        Ok(())
+    }
+
+    pub(crate) fn nest<T>(&mut self, path: &str, svc: T) -> Result<(), RouteConflict>
+    where
+        T: Service<Request<B>, Response = Response<BoxBody>, Error = E>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        T::Future: Send + 'static,
+        B: Send + 'static,
+    {
+        let pattern = PathPattern::new(path);
+        assert!(
+            !pattern
+                .segments()
+                .iter()
+                .any(|segment| matches!(segment, Segment::Wildcard(_))),
+            "Invalid nested path `{}`: nested paths cannot contain a `*wildcard`",
+            path
+        );
+        let table = self.table_mut();
+        let id = table.endpoints.len();
+        table
+            .node
+            .insert_nested(pattern.segments(), id)
+            .map_err(|overlap| table.conflict(&pattern, overlap))?;
+        table.push(pattern, svc);
+        Ok(())
+    }
+
+    fn table_mut(&mut self) -> &mut Table<B, E> {
+        Arc::make_mut(&mut self.table)
+    }
+}
+
+impl<B, E> Clone for Table<B, E> {
+    fn clone(&self) -> Self {
+        Self {
+            node: self.node.clone(),
+            endpoints: self
+                .endpoints
+                .iter()
+                .map(|endpoint| Endpoint {
+                    pattern: endpoint.pattern.clone(),
+                    svc: endpoint.svc.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<B, E> Table<B, E> {
+    fn push<T>(&mut self, pattern: PathPattern, svc: T)
+    where
+        T: Service<Request<B>, Response = Response<BoxBody>, Error = E>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        T::Future: Send + 'static,
+        B: Send + 'static,
+    {
+        self.endpoints.push(Endpoint {
+            pattern,
+            svc: Arc::new(svc),
+        });
+    }
+
+    fn conflict(&self, pattern: &PathPattern, overlap: Overlap) -> RouteConflict {
+        RouteConflict {
+            route: pattern.as_str().into(),
+            existing: self.endpoints[overlap.id].pattern.as_str().into(),
+            methods: overlap.methods,
+        }
+    }
+}
+
+// Error returned when a route would match the same requests as a route
+// that was added before it.
 #[derive(Debug, Clone)]
-pub struct Route<S, F> {
-    pub(crate) pattern: PathPattern, // parse url and match route
-    pub(crate) svc: S,               // service
-    pub(crate) fallback: F,          // back to 404 or SPA application
+pub struct RouteConflict {
+    route: Box<str>,
+    existing: Box<str>,
+    methods: MethodFilter,
+}
+
+impl RouteConflict {
+    // the route that couldn't be added
+    pub fn route(&self) -> &str {
+        &self.route
+    }
+
+    // the route that was already in the router
+    pub fn existing(&self) -> &str {
+        &self.existing
+    }
+
+    // the methods both routes handle
+    pub fn methods(&self) -> MethodFilter {
+        self.methods
+    }
+}
+
+impl fmt::Display for RouteConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Route `{}` conflicts with the existing route `{}`, ",
+            self.route, self.existing
+        )?;
+        if self.methods == MethodFilter::all() {
+            f.write_str("both match the same requests")
+        } else {
+            let methods = self.methods.to_allow_header();
+            write!(
+                f,
+                "both match {} requests",
+                methods.to_str().expect("method names are ASCII")
+            )
+        }
+    }
 }
 
-impl<S, F, B> Service<Request<B>> for Route<S, F>
+impl std::error::Error for RouteConflict {}
+
+impl<B, E> Clone for Routes<B, E> {
+    fn clone(&self) -> Self {
+        Self {
+            table: self.table.clone(),
+            fallback: self.fallback.clone(),
+            auto_options: self.auto_options,
+        }
+    }
+}
+
+impl<B, E> fmt::Debug for Routes<B, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Routes")
+            .field(
+                "routes",
+                &self
+                    .table
+                    .endpoints
+                    .iter()
+                    .map(|endpoint| endpoint.pattern.as_str())
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl<B, E> Service<Request<B>> for Routes<B, E>
 where
-    S: Service<Request<B>, Response = Response<BoxBody>> + Clone,
-    F: Service<Request<B>, Response = Response<BoxBody>, Error = S::Error> + Clone,
     B: Send + Sync + 'static,
+    E: Send + 'static,
 {
     type Response = Response<BoxBody>;
-    type Error = S::Error;
-    type Future = RouteFuture<S, F, B>;
+    type Error = E;
+    type Future = RoutesFuture<E>;
 
     fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         Poll::Ready(Ok(()))
     }
 
-    // call and return, choose the handle
+    // try every matching route until one doesn't hand the request back
+    // through `FromEmptyRouter`, then call the fallback
     fn call(&mut self, mut req: Request<B>) -> Self::Future {
-        if let Some(captures) = self.pattern.full_match(&req) {
-            insert_url_params(&mut req, captures);
-            let fut = self.svc.clone().oneshot(req);
+        let table = self.table.clone();
+        let fallback = self.fallback.clone();
+        let auto_options = self.auto_options;
+        let matches = table.node.at(req.uri().path());
 
-            RouteFuture::a(fut, self.fallback.clone())
-        } else {
-            let fut = self.fallback.clone().oneshot(req);
-            RouteFuture::b(fut)
+        let future = Box::pin(async move {
+            for match_ in matches {
+                let endpoint = &table.endpoints[match_.id];
+                let (previous, matched_path) =
+                    prepare_request(&mut req, &endpoint.pattern, &match_);
+
+                let mut res = endpoint.svc.oneshot(req).await?;
+
+                req = if let Some(ext) = res.extensions_mut().remove::<FromEmptyRouter<B>>() {
+                    ext.request
+                } else {
+                    // nested routers already added the full pattern
+                    if res.extensions().get::<MatchedPath>().is_none() {
+                        res.extensions_mut().insert(matched_path);
+                    }
+                    return Ok(res);
+                };
+
+                // undo what `prepare_request` did before trying the next route
+                if let Some(uri) = previous.uri {
+                    *req.uri_mut() = uri;
+                }
+                req.extensions_mut().insert(previous.url_params);
+                match previous.matched_path {
+                    Some(matched_path) => req.extensions_mut().insert(matched_path),
+                    None => req.extensions_mut().remove::<MatchedPath>(),
+                };
+            }
+
+            if auto_options && req.method() == Method::OPTIONS {
+                if let Some(allowed) = req.extensions().get::<AllowedMethods>() {
+                    let mut res = Response::new(crate::body::empty());
+                    *res.status_mut() = StatusCode::NO_CONTENT;
+                    res.headers_mut().insert(
+                        header::ALLOW,
+                        (allowed.0 | MethodFilter::OPTIONS).to_allow_header(),
+                    );
+                    return Ok(res);
+                }
+            }
+
+            // a route matched the path but not the method, that is a 405
+            // rather than something for the fallback
+            match fallback {
+                Some(fallback) if req.extensions().get::<NoMethodMatch>().is_none() => {
+                    fallback.oneshot(req).await
+                }
+                _ => EmptyRouter::not_found().oneshot(req).await,
+            }
+        });
+
+        RoutesFuture { future }
+    }
+}
+
+// What `prepare_request` replaced, to be put back if the route doesn't
+// handle the request.
+struct Previous {
+    uri: Option<Uri>,
+    url_params: Option<UrlParams>,
+    matched_path: Option<MatchedPath>,
+}
+
+// Insert the url params and matched path of `match_` and strip the prefix
+// of nested routes.
+fn prepare_request<B>(
+    req: &mut Request<B>,
+    pattern: &PathPattern,
+    match_: &Match,
+) -> (Previous, MatchedPath) {
+    let path = req.uri().path();
+    let captures = pattern
+        .param_names()
+        .zip(&match_.params)
+        .map(|(name, &(start, end))| (name.to_owned(), path[start..end].to_owned()))
+        .collect::<Vec<_>>();
+
+    let url_params = req
+        .extensions()
+        .get::<Option<UrlParams>>()
+        .cloned()
+        .flatten();
+
+    let uri = match_.prefix_len.map(|prefix_len| {
+        if req.extensions().get::<OriginalUri>().is_none() {
+            let original_uri = OriginalUri(req.uri().clone());
+            req.extensions_mut().insert(original_uri);
+        }
+
+        let without_prefix = strip_prefix(req.uri(), prefix_len);
+        std::mem::replace(req.uri_mut(), without_prefix)
+    });
+
+    insert_url_params(req, captures);
+
+    // inside a nested router the pattern is relative to the nest prefix
+    let previous_matched_path = req.extensions().get::<MatchedPath>().cloned();
+    let matched_path = match &previous_matched_path {
+        Some(prefix) => {
+            let prefix = prefix.as_str().trim_end_matches('/');
+            MatchedPath(format!("{}{}", prefix, pattern.as_str()).into())
         }
+        None => MatchedPath(pattern.as_str().into()),
+    };
+    req.extensions_mut().insert(matched_path.clone());
+
+    let previous = Previous {
+        uri,
+        url_params,
+        matched_path: previous_matched_path,
+    };
+    (previous, matched_path)
+}
+
+fn strip_prefix(uri: &Uri, prefix_len: usize) -> Uri {
+    let path_and_query = uri.path_and_query().map(|path_and_query| {
+        let path = &path_and_query.path()[prefix_len..];
+
+        let path = if path.starts_with('/') {
+            Cow::Borrowed(path)
+        } else {
+            Cow::Owned(format!("/{}", path))
+        };
+
+        let path_and_query = if let Some(query) = path_and_query.query() {
+            format!("{}?{}", path, query)
+        } else {
+            path.into_owned()
+        };
+
+        path_and_query
+            .parse::<PathAndQuery>()
+            .expect("stripping a prefix from a valid uri yields a valid uri")
+    });
+
+    let mut parts = http::uri::Parts::default();
+    parts.scheme = uri.scheme().cloned();
+    parts.authority = uri.authority().cloned();
+    parts.path_and_query = path_and_query;
+
+    Uri::from_parts(parts).expect("stripping a prefix from a valid uri yields a valid uri")
+}
+
+// Object safe version of `Service + Clone` so routes of different types
+// can live in the same table.
+trait CloneOneshot<B, E>: Send + Sync {
+    fn oneshot(&self, req: Request<B>) -> BoxFuture<'static, Result<Response<BoxBody>, E>>;
+}
+
+impl<S, B, E> CloneOneshot<B, E> for S
+where
+    S: Service<Request<B>, Response = Response<BoxBody>, Error = E> + Clone + Send + Sync + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    fn oneshot(&self, req: Request<B>) -> BoxFuture<'static, Result<Response<BoxBody>, E>> {
+        Box::pin(self.clone().oneshot(req))
     }
 }
 
-#[derive(Debug)]
-pub(crate) struct UrlParams(pub(crate) Vec<(ByteStr, ByteStr)>);
+// The params captured by the routes that matched, percent-decoded.
+#[derive(Debug, Clone)]
+pub(crate) enum UrlParams {
+    Params(Vec<(ByteStr, ByteStr)>),
+    // a param wasn't valid UTF-8 once decoded, `Path` rejects the request
+    InvalidUtf8InPathParam { key: ByteStr },
+}
 
 fn insert_url_params<B>(req: &mut Request<B>, params: Vec<(String, String)>) {
     let params = params
         .into_iter()
-        .map(|(k, v)| (ByteStr::new(k), ByteStr::new(v)));
+        .map(|(k, v)| match percent_decode_str(&v).decode_utf8() {
+            Ok(decoded) => Ok((ByteStr::new(k), ByteStr::new(decoded))),
+            Err(_) => Err(ByteStr::new(k)),
+        })
+        .collect::<Result<Vec<_>, _>>();
 
-    if let Some(current) = req.extensions_mut().get_mut::<Option<UrlParams>>() {
-        let mut current = current.take().unwrap();
+    let current = req
+        .extensions_mut()
+        .get_mut::<Option<UrlParams>>()
+        .and_then(Option::take);
 
-        current.0.extend(params);
-        req.extensions_mut().insert(Some(current));
-    } else {
-        req.extensions_mut()
-            .insert(Some(UrlParams(params.collect())));
-    }
+    let url_params = match (current, params) {
+        (Some(UrlParams::InvalidUtf8InPathParam { key }), _) | (_, Err(key)) => {
+            UrlParams::InvalidUtf8InPathParam { key }
+        }
+        (Some(UrlParams::Params(mut current)), Ok(params)) => {
+            current.extend(params);
+            UrlParams::Params(current)
+        }
+        (None, Ok(params)) => UrlParams::Params(params),
+    };
+    req.extensions_mut().insert(Some(url_params));
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct PathPattern(Arc<Inner>);
+
 impl PathPattern {
     pub(crate) fn new(pattern: &str) -> Self {
         assert!(pattern.starts_with('/'), "Route path must start with a `/`");
 
-        let mut capture_group_names = Vec::new();
-
-        let pattern: String = pattern
+        let segments = pattern[1..]
             .split('/')
             .map(|part| {
                 if let Some(key) = part.strip_prefix(':') {
-                    capture_group_names.push(Bytes::copy_from_slice(key.as_bytes()));
-                    Cow::Owned(format!("(?P<{}>[^/]+)", key))
+                    Segment::Param(key.into())
+                } else if let Some(key) = part.strip_prefix('*') {
+                    Segment::Wildcard(key.into())
                 } else {
-                    Cow::Borrowed(part)
+                    Segment::Static(part.into())
                 }
             })
-            .collect::<Vec<_>>()
-            .join("/");
-        let full_path_regex =
-            Regex::new(&format!("^{}", pattern)).expect("invalid regex generate from route");
+            .collect::<Box<[_]>>();
+
+        if let Some(position) = segments
+            .iter()
+            .position(|segment| matches!(segment, Segment::Wildcard(_)))
+        {
+            assert!(
+                position == segments.len() - 1,
+                "Invalid route `{}`: a `*wildcard` must be the last segment",
+                pattern
+            );
+        }
+
         Self(Arc::new(Inner {
-            full_path_regex,
-            capture_group_names: capture_group_names.into(),
+            pattern: pattern.into(),
+            segments,
         }))
     }
 
-    pub(crate) fn full_match<B>(&self, req: &Request<B>) -> Option<Captures> {
-        self.do_match(req).and_then(|match_| {
-            if match_.full_match {
-                Some(match_.captures)
-            } else {
-                None
-            }
-        })
+    pub(crate) fn as_str(&self) -> &str {
+        &self.0.pattern
     }
 
-    pub(crate) fn prefix_match<'a, B>(&self, req: &'a Request<B>) -> Option<(&'a str, Captures)> {
-        self.do_match(req)
-            .and_then(|match_| Some((match_.matched, match_.captures)))
+    pub(crate) fn segments(&self) -> &[Segment] {
+        &self.0.segments
     }
 
-    fn do_match<'a, B>(&self, req: &'a Request<B>) -> Option<Match<'a>> {
-        let path = req.uri().path();
-
-        self.0.full_path_regex.captures(path).map(|captures| {
-            let matched = captures.get(0).unwrap();
-
-            let full_match = matched.as_str() == path;
-
-            let captures = self
-                .0
-                .capture_group_names
-                .iter()
-                .map(|bytes| {
-                    std::str::from_utf8(bytes).expect("bytes were created from str is valid utf-8")
-                })
-                .filter_map(|name| captures.name(name).map(|value| (name, value.as_str())))
-                .map(|(key, value)| (key.to_string(), value.to_string()))
-                .collect::<Vec<_>>();
-
-            Match {
-                captures,
-                full_match,
-                matched: matched.as_str(),
-            }
+    pub(crate) fn param_names(&self) -> impl Iterator<Item = &str> {
+        self.0.segments.iter().filter_map(|segment| match segment {
+            Segment::Param(name) | Segment::Wildcard(name) => Some(&**name),
+            Segment::Static(_) => None,
         })
     }
 }
 
-struct Match<'a> {
-    captures: Captures,
-    full_match: bool,
-    matched: &'a str,
-}
-
-type Captures = Vec<(String, String)>;
-
 #[derive(Debug)]
 struct Inner {
-    full_path_regex: Regex,
-    // dynamic route
-    // Box<[Bytes]> is smaller than Vec
-    capture_group_names: Box<[Bytes]>,
+    pattern: Box<str>,
+    // Box<[T]> is smaller than Vec
+    segments: Box<[Segment]>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Segment {
+    Static(Box<str>),
+    // dynamic segment, `:name`
+    Param(Box<str>),
+    // the rest of the path, `*name`
+    Wildcard(Box<str>),
 }
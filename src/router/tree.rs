@@ -0,0 +1,404 @@
+// Prefix tree used to look up routes.
+//
+// Each level of the tree is one path segment. Static segments are looked
+// up by key, `:param` segments share a single child per level and
+// `*wildcard` segments end the route, so a lookup follows the path down the
+// tree instead of trying every registered route in turn.
+//
+// A lookup isn't strictly a single walk: when a segment matches both a
+// static child and the param child, both subtrees are visited, because
+// `/a/b/d` has to find `/a/:x/d` after `/a/b/c` turned out to be a dead end.
+// Only nodes that are a prefix of some registered route get visited, so
+// the cost is linear in the path length as long as static and param
+// siblings don't share deeper segments, and bounded by the size of the tree
+// in the worst case. It never depends on routes under other prefixes.
+
+use std::collections::HashMap;
+
+use super::{
+    method_filter::MethodFilter,
+    route::{PathPattern, Segment},
+};
+
+pub(crate) type RouteId = usize;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Node {
+    statics: HashMap<Box<str>, Node>,
+    param: Option<Box<Node>>,
+    // routes whose pattern ends at this node, with the methods they handle
+    routes: Vec<(RouteId, MethodFilter)>,
+    // routes ending with a `*wildcard` after this node
+    wildcards: Vec<(RouteId, MethodFilter)>,
+    // nested service mounted at this node, it handles every method
+    nested: Option<RouteId>,
+}
+
+// A route matching the request path.
+#[derive(Debug)]
+pub(crate) struct Match {
+    pub(crate) id: RouteId,
+    // byte ranges of the param values in the path, in pattern order
+    pub(crate) params: Vec<(usize, usize)>,
+    // for nested services, the length of the path prefix that was matched
+    pub(crate) prefix_len: Option<usize>,
+}
+
+// The route an inserted route conflicts with, and the methods both handle.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Overlap {
+    pub(crate) id: RouteId,
+    pub(crate) methods: MethodFilter,
+}
+
+impl Node {
+    // Insert a route handling `methods`, or return the route it conflicts
+    // with.
+    //
+    // Routes ending at the same node conflict when they handle the same
+    // method, `get(a)` and `post(b)` can be added for the same path. Param
+    // names don't take part in matching, so `/users/:id` and `/users/:name`
+    // end at the same node. A wildcard also conflicts with a service nested
+    // at the same place, it would receive requests meant for the nested
+    // service.
+    pub(crate) fn insert_route(
+        &mut self,
+        segments: &[Segment],
+        id: RouteId,
+        methods: MethodFilter,
+    ) -> Result<(), Overlap> {
+        let (node, wildcard) = match segments {
+            [rest @ .., Segment::Wildcard(_)] => (self.node_mut(rest), true),
+            _ => (self.node_mut(segments), false),
+        };
+
+        if wildcard {
+            if let Some(nested) = node.nested {
+                return Err(Overlap {
+                    id: nested,
+                    methods,
+                });
+            }
+        }
+
+        let routes = if wildcard {
+            &mut node.wildcards
+        } else {
+            &mut node.routes
+        };
+        if let Some(&(existing, existing_methods)) = routes
+            .iter()
+            .find(|(_, existing_methods)| existing_methods.intersects(methods))
+        {
+            return Err(Overlap {
+                id: existing,
+                methods: existing_methods & methods,
+            });
+        }
+        routes.push((id, methods));
+        Ok(())
+    }
+
+    // Insert a nested service, or return the route it conflicts with.
+    pub(crate) fn insert_nested(
+        &mut self,
+        segments: &[Segment],
+        id: RouteId,
+    ) -> Result<(), Overlap> {
+        // `/api/` and `/api` mount at the same place, and `/` mounts at the root
+        let segments = match segments {
+            [rest @ .., Segment::Static(last)] if last.is_empty() => rest,
+            _ => segments,
+        };
+        let node = self.node_mut(segments);
+        if let Some(existing) = node.nested {
+            return Err(Overlap {
+                id: existing,
+                methods: MethodFilter::all(),
+            });
+        }
+        if let Some(&(existing, methods)) = node.wildcards.first() {
+            return Err(Overlap {
+                id: existing,
+                methods,
+            });
+        }
+        node.nested = Some(id);
+        Ok(())
+    }
+
+    fn node_mut(&mut self, segments: &[Segment]) -> &mut Node {
+        segments.iter().fold(self, |node, segment| match segment {
+            Segment::Static(key) => node.statics.entry(key.clone()).or_default(),
+            Segment::Param(_) => node.param.get_or_insert_with(Default::default),
+            Segment::Wildcard(_) => unreachable!("wildcards are only allowed as the last segment"),
+        })
+    }
+
+    // All routes matching `path`, in the order they should be tried: full
+    // matches first (static segments before params), then wildcards and
+    // nested services (longest prefix first).
+    //
+    // Every match is collected rather than only the best one, the router
+    // falls through to the next match when a route doesn't accept the
+    // method of the request.
+    pub(crate) fn at(&self, path: &str) -> Vec<Match> {
+        let mut segments = Vec::new();
+        let mut start = 1;
+        for segment in path.get(1..).unwrap_or_default().split('/') {
+            segments.push((start, start + segment.len()));
+            start += segment.len() + 1;
+        }
+
+        let mut lookup = Lookup {
+            path,
+            segments: &segments,
+            params: Vec::new(),
+            routes: Vec::new(),
+            wildcards: Vec::new(),
+            nested: Vec::new(),
+        };
+        lookup.visit(self, 0);
+
+        lookup.wildcards.reverse();
+        lookup.nested.reverse();
+        lookup.routes.extend(lookup.wildcards);
+        lookup.routes.extend(lookup.nested);
+        lookup.routes
+    }
+}
+
+struct Lookup<'a> {
+    path: &'a str,
+    segments: &'a [(usize, usize)],
+    params: Vec<(usize, usize)>,
+    routes: Vec<Match>,
+    wildcards: Vec<Match>,
+    nested: Vec<Match>,
+}
+
+impl<'a> Lookup<'a> {
+    fn visit(&mut self, node: &Node, depth: usize) {
+        if let Some(id) = node.nested {
+            let prefix_len = if depth == 0 {
+                0
+            } else {
+                self.segments[depth - 1].1
+            };
+            self.nested.push(Match {
+                id,
+                params: self.params.clone(),
+                prefix_len: Some(prefix_len),
+            });
+        }
+
+        let (start, end) = if let Some(&segment) = self.segments.get(depth) {
+            segment
+        } else {
+            for &(id, _) in &node.routes {
+                self.routes.push(Match {
+                    id,
+                    params: self.params.clone(),
+                    prefix_len: None,
+                });
+            }
+            return;
+        };
+
+        // a wildcard captures the rest of the path, slashes included, and
+        // wildcards are reversed at the end to put the longest prefix first
+        if start < end {
+            for &(id, _) in node.wildcards.iter().rev() {
+                let mut params = self.params.clone();
+                params.push((start, self.path.len()));
+                self.wildcards.push(Match {
+                    id,
+                    params,
+                    prefix_len: None,
+                });
+            }
+        }
+
+        if let Some(child) = node.statics.get(&self.path[start..end]) {
+            self.visit(child, depth + 1);
+        }
+
+        if let Some(child) = &node.param {
+            if start < end {
+                self.params.push((start, end));
+                self.visit(child, depth + 1);
+                self.params.pop();
+            }
+        }
+    }
+}
+
+// The tree on its own, so `benches/routing.rs` can time the lookup without
+// the rest of the router. Not part of the public API.
+#[doc(hidden)]
+#[derive(Debug, Default)]
+pub struct PathTree {
+    node: Node,
+    len: usize,
+}
+
+impl PathTree {
+    pub fn insert(&mut self, path: &str) {
+        let pattern = PathPattern::new(path);
+        self.node
+            .insert_route(pattern.segments(), self.len, MethodFilter::all())
+            .unwrap_or_else(|_| panic!("Route `{}` conflicts with an existing route", path));
+        self.len += 1;
+    }
+
+    // number of routes matching `path`
+    pub fn at(&self, path: &str) -> usize {
+        self.node.at(path).len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(routes: &[&str]) -> Node {
+        let mut node = Node::default();
+        for (id, route) in routes.iter().enumerate() {
+            let pattern = PathPattern::new(route);
+            if route.len() > 1 && route.ends_with('/') {
+                node.insert_nested(pattern.segments(), id).unwrap();
+            } else {
+                node.insert_route(pattern.segments(), id, MethodFilter::all())
+                    .unwrap();
+            }
+        }
+        node
+    }
+
+    fn ids(node: &Node, path: &str) -> Vec<RouteId> {
+        node.at(path).into_iter().map(|m| m.id).collect()
+    }
+
+    #[test]
+    fn static_before_param() {
+        let node = tree(&["/users/:id", "/users/me"]);
+
+        assert_eq!(ids(&node, "/users/me"), [1, 0]);
+        assert_eq!(ids(&node, "/users/42"), [0]);
+        assert!(ids(&node, "/users").is_empty());
+        assert!(ids(&node, "/users/").is_empty());
+    }
+
+    #[test]
+    fn param_ranges() {
+        let node = tree(&["/users/:id/posts/:post"]);
+        let path = "/users/42/posts/hello";
+
+        let matches = node.at(path);
+        assert_eq!(matches.len(), 1);
+        let params = matches[0]
+            .params
+            .iter()
+            .map(|&(start, end)| &path[start..end])
+            .collect::<Vec<_>>();
+        assert_eq!(params, ["42", "hello"]);
+    }
+
+    #[test]
+    fn wildcard_after_full_matches() {
+        let node = tree(&["/assets/*path", "/assets/index.html", "/*all"]);
+
+        assert_eq!(ids(&node, "/assets/index.html"), [1, 0, 2]);
+
+        let path = "/assets/css/main.css";
+        let matches = node.at(path);
+        assert_eq!(matches.iter().map(|m| m.id).collect::<Vec<_>>(), [0, 2]);
+        let (start, end) = matches[0].params[0];
+        assert_eq!(&path[start..end], "css/main.css");
+
+        // a wildcard needs at least one character
+        assert_eq!(ids(&node, "/assets/"), [2]);
+    }
+
+    #[test]
+    fn nested_longest_prefix_first() {
+        let node = tree(&["/api/", "/api/v1/", "/api/v1/users"]);
+
+        assert_eq!(ids(&node, "/api/v1/users"), [2, 1, 0]);
+        assert_eq!(ids(&node, "/api/v2"), [0]);
+
+        let matches = node.at("/api/v1/users");
+        assert_eq!(matches[1].prefix_len, Some("/api/v1".len()));
+        assert_eq!(matches[2].prefix_len, Some("/api".len()));
+    }
+
+    #[test]
+    fn backtracks_into_param() {
+        let node = tree(&["/a/b/c", "/a/:x/d"]);
+
+        assert_eq!(ids(&node, "/a/b/c"), [0]);
+        assert_eq!(ids(&node, "/a/b/d"), [1]);
+        assert!(ids(&node, "/a/b/e").is_empty());
+    }
+
+    fn insert(
+        node: &mut Node,
+        route: &str,
+        id: RouteId,
+        methods: MethodFilter,
+    ) -> Result<(), Overlap> {
+        node.insert_route(PathPattern::new(route).segments(), id, methods)
+    }
+
+    #[test]
+    fn conflicts() {
+        let mut node = tree(&["/users/:id", "/files/*path", "/api/"]);
+        let all = MethodFilter::all();
+
+        let overlap = |id| Err(Overlap { id, methods: all });
+        assert_eq!(insert(&mut node, "/users/:name", 3, all), overlap(0));
+        assert_eq!(insert(&mut node, "/files/*rest", 3, all), overlap(1));
+        assert_eq!(insert(&mut node, "/api/*rest", 3, all), overlap(2));
+        assert_eq!(insert(&mut node, "/users/:id/posts", 3, all), Ok(()));
+    }
+
+    #[test]
+    fn split_methods() {
+        let mut node = Node::default();
+        let get = MethodFilter::GET | MethodFilter::HEAD;
+
+        assert_eq!(insert(&mut node, "/users/:id", 0, get), Ok(()));
+        assert_eq!(
+            insert(&mut node, "/users/:name", 1, MethodFilter::POST),
+            Ok(())
+        );
+        assert_eq!(ids(&node, "/users/42"), [0, 1]);
+
+        assert_eq!(
+            insert(
+                &mut node,
+                "/users/:id",
+                2,
+                MethodFilter::HEAD | MethodFilter::PUT
+            ),
+            Err(Overlap {
+                id: 0,
+                methods: MethodFilter::HEAD
+            })
+        );
+
+        assert_eq!(insert(&mut node, "/files/*path", 2, get), Ok(()));
+        assert_eq!(
+            insert(&mut node, "/files/*path", 3, MethodFilter::PUT),
+            Ok(())
+        );
+        assert_eq!(ids(&node, "/files/a/b"), [2, 3]);
+        assert_eq!(
+            insert(&mut node, "/files/*path", 4, MethodFilter::PUT),
+            Err(Overlap {
+                id: 3,
+                methods: MethodFilter::PUT
+            })
+        );
+    }
+}
@@ -0,0 +1,518 @@
+// Serve an app on a tokio `TcpListener` with graceful shutdown.
+//
+// When the shutdown signal fires the server stops accepting connections,
+// idle keep-alive connections are closed and in-flight requests are allowed
+// to finish. With a drain timeout, connections still open when it expires
+// are closed and the server returns anyway.
+//
+// With the `http2` feature, connections starting with the HTTP/2 preface
+// are served as h2c (prior knowledge), everything else as HTTP/1.1 and
+// upgraded to h2c when a request asks for it, see `h2c`.
+//
+// With the `tls` feature, `Serve::tls` serves HTTPS instead, see `tls`.
+
+use std::{
+    fmt,
+    future::{self, Future, IntoFuture, Pending},
+    pin::{pin, Pin},
+    time::Duration,
+};
+
+use futures_util::future::BoxFuture;
+use http::{Request, Response};
+use hyper::server::{
+    accept::Accept,
+    conn::{AddrIncoming, AddrStream, Http},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::watch,
+    task::JoinSet,
+};
+use tower_service::Service;
+
+use crate::{body::Body, BoxError};
+
+#[cfg(feature = "http2")]
+mod h2c;
+#[cfg(feature = "tls")]
+pub mod tls;
+
+pub fn serve<M>(listener: TcpListener, make_service: M) -> Serve<M, Pending<()>> {
+    Serve {
+        listener,
+        make_service,
+        signal: std::future::pending(),
+        drain_timeout: None,
+        http2: Http2Config::default(),
+        tls: NoTls,
+    }
+}
+
+// Marker for serving plain TCP, see `Serve::tls` for HTTPS.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoTls;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Http2Config {
+    #[cfg(feature = "http2")]
+    only: bool,
+    #[cfg(feature = "http2")]
+    max_concurrent_streams: Option<u32>,
+    #[cfg(feature = "http2")]
+    initial_stream_window_size: Option<u32>,
+    #[cfg(feature = "http2")]
+    initial_connection_window_size: Option<u32>,
+}
+
+pub struct Serve<M, F, T = NoTls> {
+    listener: TcpListener,
+    make_service: M,
+    signal: F,
+    drain_timeout: Option<Duration>,
+    http2: Http2Config,
+    tls: T,
+}
+
+impl<M, F, T> Serve<M, F, T> {
+    // start a graceful shutdown when `signal` completes, e.g. on ctrl-c
+    pub fn with_graceful_shutdown<F2>(self, signal: F2) -> Serve<M, F2, T>
+    where
+        F2: Future<Output = ()> + Send + 'static,
+    {
+        Serve {
+            listener: self.listener,
+            make_service: self.make_service,
+            signal,
+            drain_timeout: self.drain_timeout,
+            http2: self.http2,
+            tls: self.tls,
+        }
+    }
+
+    // how long open connections may take to finish after the shutdown
+    // signal, they are waited on forever by default
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = Some(timeout);
+        self
+    }
+
+    // only accept HTTP/2 connections
+    #[cfg(feature = "http2")]
+    pub fn http2_only(mut self, enabled: bool) -> Self {
+        self.http2.only = enabled;
+        self
+    }
+
+    // max number of concurrent streams per HTTP/2 connection, unlimited by
+    // default
+    #[cfg(feature = "http2")]
+    pub fn http2_max_concurrent_streams(mut self, max: u32) -> Self {
+        self.http2.max_concurrent_streams = Some(max);
+        self
+    }
+
+    #[cfg(feature = "http2")]
+    pub fn http2_initial_stream_window_size(mut self, size: u32) -> Self {
+        self.http2.initial_stream_window_size = Some(size);
+        self
+    }
+
+    #[cfg(feature = "http2")]
+    pub fn http2_initial_connection_window_size(mut self, size: u32) -> Self {
+        self.http2.initial_connection_window_size = Some(size);
+        self
+    }
+}
+
+impl<M, F, T> fmt::Debug for Serve<M, F, T>
+where
+    M: fmt::Debug,
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Serve")
+            .field("listener", &self.listener)
+            .field("make_service", &self.make_service)
+            .field("drain_timeout", &self.drain_timeout)
+            .field("http2", &self.http2)
+            .field("tls", &self.tls)
+            .finish()
+    }
+}
+
+impl<M, F, S, ME, MF, ResBody> IntoFuture for Serve<M, F, NoTls>
+where
+    M: for<'a> Service<&'a AddrStream, Response = S, Error = ME, Future = MF> + Send + 'static,
+    ME: Into<BoxError>,
+    MF: Future<Output = Result<S, ME>> + Send + 'static,
+    S: Service<Request<Body>, Response = Response<ResBody>> + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+    ResBody: http_body::Body + Send + 'static,
+    ResBody::Data: Send,
+    ResBody::Error: Into<BoxError>,
+    F: Future<Output = ()> + Send + 'static,
+{
+    type Output = Result<(), hyper::Error>;
+    type IntoFuture = ServeFuture;
+
+    fn into_future(self) -> Self::IntoFuture {
+        let future = Box::pin(async move {
+            let incoming = AddrIncoming::from_listener(self.listener)?;
+            run(
+                incoming,
+                self.make_service,
+                self.signal,
+                self.drain_timeout,
+                self.http2,
+                true,
+            )
+            .await
+        });
+
+        ServeFuture { future }
+    }
+}
+
+// Serve connections from `incoming` until shut down, shared by plain TCP
+// and TLS. `h2c` allows `Upgrade: h2c`, which is only for plain TCP.
+//
+// Every connection runs in its own task, tracked so the shutdown can ask
+// them to finish and abort the ones still running at the drain deadline.
+// Upgraded connections such as websockets are detached from hyper and
+// aren't tracked.
+async fn run<I, M, S, ME, MF, ResBody, F>(
+    incoming: I,
+    mut make_service: M,
+    signal: F,
+    drain_timeout: Option<Duration>,
+    http2: Http2Config,
+    h2c: bool,
+) -> Result<(), hyper::Error>
+where
+    I: Accept,
+    I::Error: Into<BoxError>,
+    I::Conn: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    M: for<'a> Service<&'a I::Conn, Response = S, Error = ME, Future = MF>,
+    ME: Into<BoxError>,
+    MF: Future<Output = Result<S, ME>> + Send + 'static,
+    S: Service<Request<Body>, Response = Response<ResBody>> + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+    ResBody: http_body::Body + Send + 'static,
+    ResBody::Data: Send,
+    ResBody::Error: Into<BoxError>,
+    F: Future<Output = ()>,
+{
+    let mut http = Http::new();
+    http2.apply(&mut http);
+
+    let mut incoming = Box::pin(incoming);
+    let mut signal = pin!(signal);
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let mut connections = JoinSet::new();
+
+    loop {
+        let conn = tokio::select! {
+            conn = future::poll_fn(|cx| incoming.as_mut().poll_accept(cx)) => conn,
+            () = &mut signal => break,
+            // reap finished connections so the set doesn't keep growing
+            Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+        };
+
+        let conn = match conn {
+            Some(Ok(conn)) => conn,
+            // `AddrIncoming` already backs off on errors of the listener
+            Some(Err(err)) => {
+                tracing::debug!(err = %err.into(), "failed to accept connection");
+                continue;
+            }
+            None => break,
+        };
+
+        if let Err(err) = future::poll_fn(|cx| make_service.poll_ready(cx)).await {
+            tracing::error!(err = %err.into(), "failed to make service");
+            continue;
+        }
+        let make_svc = make_service.call(&conn);
+
+        let http = http.clone();
+        let mut shutdown_rx = shutdown_rx.clone();
+        connections.spawn(async move {
+            let svc = match make_svc.await {
+                Ok(svc) => svc,
+                Err(err) => {
+                    tracing::error!(err = %err.into(), "failed to make service");
+                    return;
+                }
+            };
+
+            let result = serve_connection(http, conn, svc, &mut shutdown_rx, h2c).await;
+            if let Err(err) = result {
+                tracing::debug!(%err, "connection error");
+            }
+        });
+    }
+
+    // stop accepting and let the connections know
+    drop(incoming);
+    let _ = shutdown_tx.send(true);
+
+    let drain = async { while connections.join_next().await.is_some() {} };
+    match drain_timeout {
+        Some(timeout) => {
+            if tokio::time::timeout(timeout, drain).await.is_err() {
+                connections.shutdown().await;
+            }
+        }
+        None => drain.await,
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "http2"))]
+async fn serve_connection<C, S, ResBody>(
+    http: Http,
+    conn: C,
+    svc: S,
+    shutdown: &mut watch::Receiver<bool>,
+    _h2c: bool,
+) -> Result<(), hyper::Error>
+where
+    C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Service<Request<Body>, Response = Response<ResBody>> + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+    ResBody: http_body::Body + Send + 'static,
+    ResBody::Data: Send,
+    ResBody::Error: Into<BoxError>,
+{
+    let conn = http.serve_connection(conn, svc).with_upgrades();
+    graceful(conn, shutdown, |conn| conn.graceful_shutdown()).await
+}
+
+#[cfg(feature = "http2")]
+use self::h2c::serve_connection;
+
+// Drive `conn`, shutting it down gracefully once `shutdown` is set: the
+// requests in flight finish, then the connection is closed.
+async fn graceful<C>(
+    conn: C,
+    shutdown: &mut watch::Receiver<bool>,
+    graceful_shutdown: impl FnOnce(Pin<&mut C>),
+) -> C::Output
+where
+    C: Future,
+{
+    let mut conn = pin!(conn);
+    let shutting_down = *shutdown.borrow();
+    if !shutting_down {
+        tokio::select! {
+            output = conn.as_mut() => return output,
+            _ = shutdown.changed() => {}
+        }
+    }
+    graceful_shutdown(conn.as_mut());
+    conn.await
+}
+
+impl Http2Config {
+    #[cfg(feature = "http2")]
+    fn apply(self, http: &mut Http) {
+        http.http2_only(self.only)
+            .http2_max_concurrent_streams(self.max_concurrent_streams)
+            .http2_initial_stream_window_size(self.initial_stream_window_size)
+            .http2_initial_connection_window_size(self.initial_connection_window_size);
+    }
+
+    #[cfg(not(feature = "http2"))]
+    fn apply(self, _http: &mut Http) {}
+}
+
+opaque_future! {
+    pub type ServeFuture = BoxFuture<'static, Result<(), hyper::Error>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        sync::{oneshot, Notify},
+    };
+
+    use super::*;
+    use crate::{handler::get, Router};
+
+    // Serve a router whose only route sleeps for `delay`, send it a request
+    // and start the shutdown once the handler is running. Returns what the
+    // client read and whether the server had returned within `wait`.
+    async fn shutdown_during_request(
+        delay: Duration,
+        drain_timeout: Duration,
+        wait: Duration,
+    ) -> (String, bool) {
+        let started = Arc::new(Notify::new());
+        let app = Router::new().route(
+            "/",
+            get({
+                let started = started.clone();
+                move || async move {
+                    started.notify_one();
+                    tokio::time::sleep(delay).await;
+                    "done"
+                }
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (signal_tx, signal_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(
+            serve(listener, app.into_make_service())
+                .with_graceful_shutdown(async {
+                    let _ = signal_rx.await;
+                })
+                .drain_timeout(drain_timeout)
+                .into_future(),
+        );
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        started.notified().await;
+        signal_tx.send(()).unwrap();
+
+        let returned = tokio::time::timeout(wait, server).await.is_ok();
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await.unwrap();
+        (res, returned)
+    }
+
+    #[tokio::test]
+    async fn drain_waits_for_requests_in_flight() {
+        let (res, returned) = shutdown_during_request(
+            Duration::from_millis(50),
+            Duration::from_secs(5),
+            Duration::from_secs(5),
+        )
+        .await;
+
+        assert!(returned);
+        assert!(res.starts_with("HTTP/1.1 200 OK"), "{:?}", res);
+        assert!(res.ends_with("done"), "{:?}", res);
+    }
+
+    #[tokio::test]
+    async fn drain_timeout_cuts_off_slow_requests() {
+        let (res, returned) = shutdown_during_request(
+            Duration::from_secs(60),
+            Duration::from_millis(50),
+            Duration::from_secs(5),
+        )
+        .await;
+
+        // the connection was closed without a response
+        assert!(returned);
+        assert_eq!(res, "");
+    }
+
+    #[cfg(feature = "http2")]
+    async fn spawn_server() -> std::net::SocketAddr {
+        let app = Router::new().route("/", get(|| async { "hello" }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, app.into_make_service()).into_future());
+        addr
+    }
+
+    #[cfg(feature = "http2")]
+    #[tokio::test]
+    async fn router_over_h2() {
+        let addr = spawn_server().await;
+        let client = hyper::Client::builder()
+            .http2_only(true)
+            .build_http::<hyper::Body>();
+
+        for method in [http::Method::GET, http::Method::HEAD] {
+            let req = Request::builder()
+                .method(method.clone())
+                .uri(format!("http://{}/", addr))
+                .body(hyper::Body::empty())
+                .unwrap();
+            let res = client.request(req).await.unwrap();
+            assert_eq!(res.version(), http::Version::HTTP_2);
+            assert_eq!(res.status(), http::StatusCode::OK);
+
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let expected: &[u8] = if method == http::Method::HEAD {
+                b""
+            } else {
+                b"hello"
+            };
+            assert_eq!(body, expected, "{}", method);
+        }
+    }
+
+    #[cfg(feature = "http2")]
+    #[tokio::test]
+    async fn h2c_upgrade() {
+        let addr = spawn_server().await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                b"GET / HTTP/1.1\r\n\
+                  host: localhost\r\n\
+                  connection: upgrade, http2-settings\r\n\
+                  upgrade: h2c\r\n\
+                  http2-settings: \r\n\r\n",
+            )
+            .await
+            .unwrap();
+
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(stream.read_u8().await.unwrap());
+        }
+        let head = String::from_utf8(head).unwrap();
+        assert!(
+            head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"),
+            "{}",
+            head
+        );
+
+        // preface and an empty SETTINGS frame
+        stream
+            .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0")
+            .await
+            .unwrap();
+
+        // the response to the upgrade request comes on stream 1
+        let mut body = Vec::new();
+        loop {
+            let mut header = [0; 9];
+            stream.read_exact(&mut header).await.unwrap();
+            let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+            let (kind, flags) = (header[3], header[4]);
+            let stream_id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]);
+            let mut payload = vec![0; len];
+            stream.read_exact(&mut payload).await.unwrap();
+
+            // DATA
+            if kind == 0 && stream_id == 1 {
+                body.extend_from_slice(&payload);
+                if flags & 0x1 != 0 {
+                    break;
+                }
+            }
+        }
+        assert_eq!(body, b"hello");
+    }
+}
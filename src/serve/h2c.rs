@@ -0,0 +1,462 @@
+// HTTP/1.1 `Upgrade: h2c` (RFC 7540, section 3.2).
+//
+// The request asking for the upgrade has to be answered over HTTP/2 on
+// stream 1, and hyper's HTTP/2 server can't be handed a request. So
+// `H2cUpgrade` answers it with `101 Switching Protocols` without calling
+// the service, and once the connection is upgraded the request is replayed
+// to the HTTP/2 server as a HEADERS frame for stream 1, right after the
+// client's preface.
+//
+// Only requests without a body are upgraded, others are answered over
+// HTTP/1.1 as the upgrade is optional for the server. The settings in
+// `HTTP2-Settings` aren't applied, the client sends them again in its
+// preface.
+
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use bytes::{Buf, Bytes};
+use http::{
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    request::Parts,
+    Request, Response, StatusCode, Version,
+};
+use hyper::{server::conn::Http, upgrade::OnUpgrade};
+use pin_project_lite::pin_project;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf},
+    sync::watch,
+};
+use tower_service::Service;
+
+use super::graceful;
+use crate::{body::Body, BoxError};
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// the smallest max frame size a peer can have
+const MAX_FRAME_SIZE: usize = 16_384;
+
+const HEADERS: u8 = 0x1;
+const SETTINGS: u8 = 0x4;
+const CONTINUATION: u8 = 0x9;
+const END_STREAM: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+
+// Serve a connection, upgrading it to HTTP/2 when a request asks for it
+// and `h2c` is set.
+pub(super) async fn serve_connection<C, S, ResBody>(
+    http: Http,
+    conn: C,
+    svc: S,
+    shutdown: &mut watch::Receiver<bool>,
+    h2c: bool,
+) -> Result<(), hyper::Error>
+where
+    C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Service<Request<Body>, Response = Response<ResBody>> + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+    ResBody: http_body::Body + Send + 'static,
+    ResBody::Data: Send,
+    ResBody::Error: Into<BoxError>,
+{
+    if !h2c {
+        let conn = http.serve_connection(conn, svc).with_upgrades();
+        return graceful(conn, shutdown, |conn| conn.graceful_shutdown()).await;
+    }
+
+    let (svc, upgrade) = H2cUpgrade::new(svc);
+    let conn = http.serve_connection(conn, svc).with_upgrades();
+    graceful(conn, shutdown, |conn| conn.graceful_shutdown()).await?;
+
+    let upgrade = upgrade.lock().unwrap().take();
+    let Upgrade {
+        on_upgrade,
+        parts,
+        svc,
+    } = match upgrade {
+        Some(upgrade) => upgrade,
+        None => return Ok(()),
+    };
+
+    let io = on_upgrade.await?;
+    let io = tokio::select! {
+        io = replay(io, &parts) => io,
+        _ = shutdown.changed() => return Ok(()),
+    };
+    let io = match io {
+        Ok(io) => io,
+        Err(err) => {
+            tracing::debug!(%err, "h2c upgrade failed");
+            return Ok(());
+        }
+    };
+
+    let mut http = http;
+    http.http2_only(true);
+    let conn = http.serve_connection(io, svc);
+    graceful(conn, shutdown, |conn| conn.graceful_shutdown()).await
+}
+
+struct H2cUpgrade<S> {
+    // taken once the connection is upgraded
+    inner: Option<S>,
+    upgrade: UpgradeSlot<S>,
+}
+
+type UpgradeSlot<S> = Arc<Mutex<Option<Upgrade<S>>>>;
+
+// What the HTTP/2 connection needs once the 101 response has been sent.
+struct Upgrade<S> {
+    on_upgrade: OnUpgrade,
+    parts: Parts,
+    svc: S,
+}
+
+impl<S> H2cUpgrade<S> {
+    fn new(inner: S) -> (Self, UpgradeSlot<S>) {
+        let upgrade = Arc::new(Mutex::new(None));
+        let svc = Self {
+            inner: Some(inner),
+            upgrade: upgrade.clone(),
+        };
+        (svc, upgrade)
+    }
+}
+
+impl<S, ResBody> Service<Request<Body>> for H2cUpgrade<S>
+where
+    S: Service<Request<Body>, Response = Response<ResBody>>,
+{
+    type Response = Response<H2cBody<ResBody>>;
+    type Error = S::Error;
+    type Future = H2cFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match &mut self.inner {
+            Some(inner) => inner.poll_ready(cx),
+            // hyper doesn't read more requests after a 101
+            None => Poll::Pending,
+        }
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        if !is_h2c_upgrade(&req) {
+            let inner = self
+                .inner
+                .as_mut()
+                .expect("request received after upgrading to h2c");
+            return H2cFuture {
+                future: Some(inner.call(req)),
+            };
+        }
+
+        let on_upgrade = hyper::upgrade::on(&mut req);
+        let (parts, _) = req.into_parts();
+        *self.upgrade.lock().unwrap() = Some(Upgrade {
+            on_upgrade,
+            parts,
+            svc: self.inner.take().expect("upgraded to h2c twice"),
+        });
+
+        H2cFuture { future: None }
+    }
+}
+
+fn is_h2c_upgrade<B>(req: &Request<B>) -> bool {
+    let headers = req.headers();
+    let has_token = |name, token: &str| {
+        headers.get_all(name).iter().any(|value| {
+            value.to_str().is_ok_and(|value| {
+                value
+                    .split(',')
+                    .any(|part| part.trim().eq_ignore_ascii_case(token))
+            })
+        })
+    };
+    let no_body = !headers.contains_key(header::TRANSFER_ENCODING)
+        && headers
+            .get(header::CONTENT_LENGTH)
+            .is_none_or(|len| len == "0");
+
+    req.version() == Version::HTTP_11
+        && has_token(header::UPGRADE, "h2c")
+        && has_token(header::CONNECTION, "upgrade")
+        && has_token(header::CONNECTION, "http2-settings")
+        && headers.get_all("http2-settings").iter().count() == 1
+        && no_body
+}
+
+pin_project! {
+    struct H2cFuture<F> {
+        // `None` for the upgrade request, answered with a 101
+        #[pin]
+        future: Option<F>,
+    }
+}
+
+impl<F, B, E> Future for H2cFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<H2cBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let future = match self.project().future.as_pin_mut() {
+            Some(future) => future,
+            None => {
+                let mut res = Response::new(H2cBody { inner: None });
+                *res.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+                res.headers_mut()
+                    .insert(header::CONNECTION, HeaderValue::from_static("upgrade"));
+                res.headers_mut()
+                    .insert(header::UPGRADE, HeaderValue::from_static("h2c"));
+                return Poll::Ready(Ok(res));
+            }
+        };
+
+        let res = futures_util::ready!(future.poll(cx))?;
+        Poll::Ready(Ok(res.map(|body| H2cBody { inner: Some(body) })))
+    }
+}
+
+pin_project! {
+    struct H2cBody<B> {
+        // `None` for the 101 response
+        #[pin]
+        inner: Option<B>,
+    }
+}
+
+impl<B> http_body::Body for H2cBody<B>
+where
+    B: http_body::Body,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        match self.project().inner.as_pin_mut() {
+            Some(inner) => inner.poll_data(cx),
+            None => Poll::Ready(None),
+        }
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        match self.project().inner.as_pin_mut() {
+            Some(inner) => inner.poll_trailers(cx),
+            None => Poll::Ready(Ok(None)),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner
+            .as_ref()
+            .is_none_or(http_body::Body::is_end_stream)
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.as_ref().map_or_else(
+            || http_body::SizeHint::with_exact(0),
+            http_body::Body::size_hint,
+        )
+    }
+}
+
+// Read the client's preface and first SETTINGS frame, then make the HTTP/2
+// server read them followed by the upgrade request on stream 1.
+async fn replay<I>(mut io: I, parts: &Parts) -> io::Result<Replay<I>>
+where
+    I: AsyncRead + Unpin,
+{
+    let mut prefix = vec![0; PREFACE.len() + 9];
+    io.read_exact(&mut prefix).await?;
+    if &prefix[..PREFACE.len()] != PREFACE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid HTTP/2 preface",
+        ));
+    }
+
+    let frame_header = &prefix[PREFACE.len()..];
+    if frame_header[3] != SETTINGS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "HTTP/2 preface doesn't start with SETTINGS",
+        ));
+    }
+    let len = u32::from_be_bytes([0, frame_header[0], frame_header[1], frame_header[2]]);
+    let start = prefix.len();
+    prefix.resize(start + len as usize, 0);
+    io.read_exact(&mut prefix[start..]).await?;
+
+    encode_request(parts, &mut prefix);
+
+    Ok(Replay {
+        prefix: prefix.into(),
+        io,
+    })
+}
+
+// Encode the request as HEADERS and CONTINUATION frames for stream 1,
+// using HPACK literals that don't touch the dynamic table.
+fn encode_request(parts: &Parts, dst: &mut Vec<u8>) {
+    let mut block = Vec::new();
+
+    let authority = parts
+        .uri
+        .authority()
+        .map(|authority| authority.as_str().as_bytes())
+        .or_else(|| parts.headers.get(header::HOST).map(HeaderValue::as_bytes));
+    let path = parts
+        .uri
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str());
+
+    encode_field(b":method", parts.method.as_str().as_bytes(), &mut block);
+    encode_field(b":scheme", b"http", &mut block);
+    encode_field(b":path", path.as_bytes(), &mut block);
+    if let Some(authority) = authority {
+        encode_field(b":authority", authority, &mut block);
+    }
+
+    for (name, value) in &parts.headers {
+        if !is_connection_specific(name) {
+            encode_field(name.as_str().as_bytes(), value.as_bytes(), &mut block);
+        }
+    }
+
+    let mut chunks = block.chunks(MAX_FRAME_SIZE).peekable();
+    let mut kind = HEADERS;
+    let mut flags = END_STREAM;
+    // an empty block still needs a HEADERS frame
+    let mut chunk = chunks.next().unwrap_or_default();
+    loop {
+        let last = chunks.peek().is_none();
+        if last {
+            flags |= END_HEADERS;
+        }
+
+        dst.extend_from_slice(&(chunk.len() as u32).to_be_bytes()[1..]);
+        dst.extend_from_slice(&[kind, flags]);
+        dst.extend_from_slice(&1u32.to_be_bytes());
+        dst.extend_from_slice(chunk);
+
+        match chunks.next() {
+            Some(next) => chunk = next,
+            None => break,
+        }
+        kind = CONTINUATION;
+        flags = 0;
+    }
+}
+
+// Literal header field without indexing, new name.
+fn encode_field(name: &[u8], value: &[u8], dst: &mut Vec<u8>) {
+    dst.push(0);
+    encode_string(name, dst);
+    encode_string(value, dst);
+}
+
+// String literal without Huffman coding, the length as a 7-bit prefix
+// integer.
+fn encode_string(s: &[u8], dst: &mut Vec<u8>) {
+    const PREFIX_MAX: usize = 0x7f;
+
+    if s.len() < PREFIX_MAX {
+        dst.push(s.len() as u8);
+    } else {
+        dst.push(PREFIX_MAX as u8);
+        let mut rest = s.len() - PREFIX_MAX;
+        while rest >= 0x80 {
+            dst.push((rest & 0x7f) as u8 | 0x80);
+            rest >>= 7;
+        }
+        dst.push(rest as u8);
+    }
+    dst.extend_from_slice(s);
+}
+
+// headers HTTP/2 doesn't allow, or that only applied to the HTTP/1.1
+// request
+fn is_connection_specific(name: &HeaderName) -> bool {
+    name == header::CONNECTION
+        || name == header::UPGRADE
+        || name == header::HOST
+        || name == header::TRANSFER_ENCODING
+        || name == header::TE
+        || name == "http2-settings"
+        || name == "keep-alive"
+        || name == "proxy-connection"
+}
+
+// The upgraded connection, reading `prefix` before the rest of the stream.
+struct Replay<I> {
+    prefix: Bytes,
+    io: I,
+}
+
+impl<I> AsyncRead for Replay<I>
+where
+    I: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.prefix.is_empty() {
+            return Pin::new(&mut self.io).poll_read(cx, buf);
+        }
+
+        let len = self.prefix.len().min(buf.remaining());
+        buf.put_slice(&self.prefix[..len]);
+        self.prefix.advance(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<I> AsyncWrite for Replay<I>
+where
+    I: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
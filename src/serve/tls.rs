@@ -0,0 +1,661 @@
+// HTTPS with rustls.
+//
+// Connections are accepted on the TCP listener and the TLS handshakes run
+// concurrently, so a slow client can't hold up the others. The config is
+// read for every handshake, reloading it only affects new connections.
+
+use std::{
+    fmt,
+    fs::File,
+    future::{Future, IntoFuture},
+    io::{self, BufReader},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+
+use futures_util::{
+    future::{self, BoxFuture},
+    stream::{FuturesUnordered, StreamExt},
+};
+use http::{Request, Response};
+use hyper::server::{
+    accept::Accept,
+    conn::{AddrIncoming, AddrStream},
+};
+use pin_project_lite::pin_project;
+pub use rustls;
+use rustls::{
+    server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient},
+    Certificate, PrivateKey, RootCertStore, ServerConfig,
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tower_http::add_extension::AddExtension;
+use tower_service::Service;
+
+use super::{run, Serve, ServeFuture};
+use crate::{
+    body::Body,
+    extract::builtin::{connect_info::Connected, peer_certificates::PeerCertificates},
+    BoxError,
+};
+
+// clients that haven't finished the handshake by then are dropped
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const DEFAULT_RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+impl<M, F> Serve<M, F> {
+    // serve HTTPS instead of plain HTTP
+    pub fn tls(self, config: RustlsConfig) -> Serve<M, F, RustlsConfig> {
+        Serve {
+            listener: self.listener,
+            make_service: self.make_service,
+            signal: self.signal,
+            drain_timeout: self.drain_timeout,
+            http2: self.http2,
+            tls: config,
+        }
+    }
+}
+
+// Shared handle to the rustls config used for new connections.
+//
+// Configs loaded from PEM files are reloaded while serving when the files
+// change on disk. A config that fails to load is logged and the previous one
+// is kept.
+//
+// PEM files don't ask for client certificates unless `ClientAuth` is given
+// with `from_pem_file_with_client_auth`, other rustls settings need
+// `from_config`.
+#[derive(Clone)]
+pub struct RustlsConfig {
+    config: Arc<RwLock<Arc<ServerConfig>>>,
+    files: Option<Arc<PemFiles>>,
+    client_auth: Option<ClientAuth>,
+    reload_interval: Duration,
+    handshake_timeout: Duration,
+}
+
+struct PemFiles {
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+impl RustlsConfig {
+    // ALPN protocols are set to h2 (with the `http2` feature) and http/1.1
+    // when the config doesn't list any
+    pub fn from_config(config: Arc<ServerConfig>) -> Self {
+        Self {
+            config: Arc::new(RwLock::new(with_alpn(config))),
+            files: None,
+            client_auth: None,
+            reload_interval: DEFAULT_RELOAD_INTERVAL,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+        }
+    }
+
+    pub fn from_pem_file(cert: impl AsRef<Path>, key: impl AsRef<Path>) -> io::Result<Self> {
+        Self::pem_file(cert.as_ref(), key.as_ref(), None)
+    }
+
+    // like `from_pem_file`, asking clients for a certificate (mutual TLS)
+    pub fn from_pem_file_with_client_auth(
+        cert: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+        client_auth: ClientAuth,
+    ) -> io::Result<Self> {
+        Self::pem_file(cert.as_ref(), key.as_ref(), Some(client_auth))
+    }
+
+    fn pem_file(cert: &Path, key: &Path, client_auth: Option<ClientAuth>) -> io::Result<Self> {
+        let files = PemFiles {
+            cert: cert.to_owned(),
+            key: key.to_owned(),
+        };
+        let config = config_from_pem_file(&files.cert, &files.key, client_auth.clone())?;
+
+        Ok(Self {
+            config: Arc::new(RwLock::new(config)),
+            files: Some(Arc::new(files)),
+            client_auth,
+            reload_interval: DEFAULT_RELOAD_INTERVAL,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+        })
+    }
+
+    // how often the PEM files are checked for changes, 10 seconds by default
+    pub fn reload_interval(mut self, interval: Duration) -> Self {
+        self.reload_interval = interval;
+        self
+    }
+
+    // clients that haven't finished the handshake by then are dropped, 10
+    // seconds by default
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    pub fn get(&self) -> Arc<ServerConfig> {
+        self.config.read().unwrap().clone()
+    }
+
+    pub fn reload_from_config(&self, config: Arc<ServerConfig>) {
+        *self.config.write().unwrap() = with_alpn(config);
+    }
+
+    // keeps the `ClientAuth` given to `from_pem_file_with_client_auth`
+    pub fn reload_from_pem_file(
+        &self,
+        cert: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+    ) -> io::Result<()> {
+        let config = config_from_pem_file(cert.as_ref(), key.as_ref(), self.client_auth.clone())?;
+        *self.config.write().unwrap() = config;
+        Ok(())
+    }
+
+    // Completes never, reloads the PEM files whenever their modification
+    // time changes.
+    async fn watch(self) {
+        let files = match &self.files {
+            Some(files) => files.clone(),
+            None => return future::pending().await,
+        };
+
+        let modified = |files: &PemFiles| -> io::Result<(SystemTime, SystemTime)> {
+            Ok((
+                std::fs::metadata(&files.cert)?.modified()?,
+                std::fs::metadata(&files.key)?.modified()?,
+            ))
+        };
+
+        let mut last = modified(&files).ok();
+        let mut interval = tokio::time::interval(self.reload_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+
+            let current = match modified(&files) {
+                Ok(current) => Some(current),
+                Err(err) => {
+                    tracing::warn!(%err, "failed to read TLS certificate files");
+                    continue;
+                }
+            };
+            if current == last {
+                continue;
+            }
+
+            match self.reload_from_pem_file(&files.cert, &files.key) {
+                Ok(()) => {
+                    tracing::info!(cert = ?files.cert, "reloaded TLS certificate");
+                    last = current;
+                }
+                Err(err) => tracing::warn!(%err, "failed to reload TLS certificate"),
+            }
+        }
+    }
+}
+
+// Client certificates asked for by configs loaded from PEM files.
+#[derive(Debug, Clone)]
+pub enum ClientAuth {
+    // clients have to send a certificate signed by one of the roots
+    Required(RootCertStore),
+    // clients may send one, `PeerCertificates` is empty when they don't
+    Optional(RootCertStore),
+}
+
+impl fmt::Debug for RustlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RustlsConfig")
+            .field("cert", &self.files.as_ref().map(|files| &files.cert))
+            .field("key", &self.files.as_ref().map(|files| &files.key))
+            .field("client_auth", &self.client_auth.is_some())
+            .field("reload_interval", &self.reload_interval)
+            .field("handshake_timeout", &self.handshake_timeout)
+            .finish()
+    }
+}
+
+fn with_alpn(config: Arc<ServerConfig>) -> Arc<ServerConfig> {
+    if !config.alpn_protocols.is_empty() {
+        return config;
+    }
+
+    let mut config = ServerConfig::clone(&config);
+    config.alpn_protocols = alpn_protocols();
+    Arc::new(config)
+}
+
+fn alpn_protocols() -> Vec<Vec<u8>> {
+    let mut protocols = Vec::new();
+    if cfg!(feature = "http2") {
+        protocols.push(b"h2".to_vec());
+    }
+    protocols.push(b"http/1.1".to_vec());
+    protocols
+}
+
+fn config_from_pem_file(
+    cert: &Path,
+    key: &Path,
+    client_auth: Option<ClientAuth>,
+) -> io::Result<Arc<ServerConfig>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert)?))?
+        .into_iter()
+        .map(Certificate)
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        return Err(invalid_data(format!(
+            "no certificates found in `{}`",
+            cert.display()
+        )));
+    }
+
+    let key = rustls_pemfile::read_all(&mut BufReader::new(File::open(key)?))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| invalid_data(format!("no private key found in `{}`", key.display())))?;
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match client_auth {
+        Some(ClientAuth::Required(roots)) => {
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+        }
+        Some(ClientAuth::Optional(roots)) => builder
+            .with_client_cert_verifier(AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed()),
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_single_cert(certs, key).map_err(invalid_data)?;
+    config.alpn_protocols = alpn_protocols();
+
+    Ok(Arc::new(config))
+}
+
+fn invalid_data<E>(err: E) -> io::Error
+where
+    E: Into<BoxError>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+impl<M, F, S, ME, MF, ResBody> IntoFuture for Serve<M, F, RustlsConfig>
+where
+    M: for<'a> Service<&'a TlsStream<AddrStream>, Response = S, Error = ME, Future = MF>
+        + Send
+        + 'static,
+    ME: Into<BoxError>,
+    MF: Future<Output = Result<S, ME>> + Send + 'static,
+    S: Service<Request<Body>, Response = Response<ResBody>> + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+    ResBody: http_body::Body + Send + 'static,
+    ResBody::Data: Send,
+    ResBody::Error: Into<BoxError>,
+    F: Future<Output = ()> + Send + 'static,
+{
+    type Output = Result<(), hyper::Error>;
+    type IntoFuture = ServeFuture;
+
+    fn into_future(self) -> Self::IntoFuture {
+        let future = Box::pin(async move {
+            let incoming = TlsIncoming {
+                incoming: AddrIncoming::from_listener(self.listener)?,
+                config: self.tls.clone(),
+                handshakes: FuturesUnordered::new(),
+            };
+            let server = run(
+                incoming,
+                AddPeerCertificates(self.make_service),
+                self.signal,
+                self.drain_timeout,
+                self.http2,
+                false,
+            );
+
+            match future::select(Box::pin(server), Box::pin(self.tls.watch())).await {
+                future::Either::Left((result, _)) => result,
+                future::Either::Right(((), _)) => unreachable!("watching the config never ends"),
+            }
+        });
+
+        ServeFuture { future }
+    }
+}
+
+impl Connected<&TlsStream<AddrStream>> for SocketAddr {
+    fn connect_info(target: &TlsStream<AddrStream>) -> Self {
+        target.get_ref().0.remote_addr()
+    }
+}
+
+struct TlsIncoming {
+    incoming: AddrIncoming,
+    config: RustlsConfig,
+    handshakes: FuturesUnordered<BoxFuture<'static, io::Result<TlsStream<AddrStream>>>>,
+}
+
+impl Accept for TlsIncoming {
+    type Conn = TlsStream<AddrStream>;
+    type Error = io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let this = &mut *self;
+
+        while let Poll::Ready(stream) = Pin::new(&mut this.incoming).poll_accept(cx) {
+            let stream = match stream {
+                Some(Ok(stream)) => stream,
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => return Poll::Ready(None),
+            };
+
+            let acceptor = TlsAcceptor::from(this.config.get());
+            let timeout = this.config.handshake_timeout;
+            this.handshakes.push(Box::pin(async move {
+                tokio::time::timeout(timeout, acceptor.accept(stream))
+                    .await
+                    .map_err(|_| {
+                        io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out")
+                    })?
+            }));
+        }
+
+        loop {
+            match this.handshakes.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(stream))) => return Poll::Ready(Some(Ok(stream))),
+                // a failed handshake only affects that client
+                Poll::Ready(Some(Err(err))) => tracing::debug!(%err, "TLS handshake failed"),
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+// Makes the client certificates of each connection available to the
+// `PeerCertificates` extractor.
+struct AddPeerCertificates<M>(M);
+
+impl<'a, M, S, ME, MF> Service<&'a TlsStream<AddrStream>> for AddPeerCertificates<M>
+where
+    M: Service<&'a TlsStream<AddrStream>, Response = S, Error = ME, Future = MF>,
+    MF: Future<Output = Result<S, ME>>,
+{
+    type Response = AddExtension<S, PeerCertificates>;
+    type Error = ME;
+    type Future = AddPeerCertificatesFuture<MF>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, target: &'a TlsStream<AddrStream>) -> Self::Future {
+        let certs = target
+            .get_ref()
+            .1
+            .peer_certificates()
+            .map(<[Certificate]>::to_vec)
+            .unwrap_or_default();
+
+        AddPeerCertificatesFuture {
+            future: self.0.call(target),
+            certs: Some(PeerCertificates(certs)),
+        }
+    }
+}
+
+pin_project! {
+    struct AddPeerCertificatesFuture<F> {
+        #[pin]
+        future: F,
+        certs: Option<PeerCertificates>,
+    }
+}
+
+impl<F, S, E> Future for AddPeerCertificatesFuture<F>
+where
+    F: Future<Output = Result<S, E>>,
+{
+    type Output = Result<AddExtension<S, PeerCertificates>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let svc = futures_util::ready!(this.future.poll(cx))?;
+        let certs = this.certs.take().expect("future polled after completion");
+        Poll::Ready(Ok(AddExtension::new(svc, certs)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{BasicConstraints, CertificateParams, IsCa};
+    use rustls::ClientConfig;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+    use tokio_rustls::TlsConnector;
+
+    use super::*;
+    use crate::{handler::get, serve, Router};
+
+    struct Pki {
+        ca: rcgen::Certificate,
+        dir: PathBuf,
+    }
+
+    impl Pki {
+        fn new(name: &str) -> Self {
+            let mut params = CertificateParams::new(Vec::new());
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let dir =
+                std::env::temp_dir().join(format!("nexus-tls-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+
+            Self {
+                ca: rcgen::Certificate::from_params(params).unwrap(),
+                dir,
+            }
+        }
+
+        fn roots(&self) -> RootCertStore {
+            let mut roots = RootCertStore::empty();
+            roots
+                .add(&Certificate(self.ca.serialize_der().unwrap()))
+                .unwrap();
+            roots
+        }
+
+        // a certificate for `name` signed by the CA, and its key
+        fn issue(&self, name: &str) -> (Certificate, PrivateKey) {
+            let cert =
+                rcgen::Certificate::from_params(CertificateParams::new(vec![name.into()])).unwrap();
+            (
+                Certificate(cert.serialize_der_with_signer(&self.ca).unwrap()),
+                PrivateKey(cert.serialize_private_key_der()),
+            )
+        }
+
+        // issue a server certificate into `cert.pem` and `key.pem`
+        fn write_server_pem(&self) -> (PathBuf, PathBuf, Certificate) {
+            let cert =
+                rcgen::Certificate::from_params(CertificateParams::new(vec!["localhost".into()]))
+                    .unwrap();
+            let pem = cert.serialize_pem_with_signer(&self.ca).unwrap();
+            let der = rustls_pemfile::certs(&mut pem.as_bytes())
+                .unwrap()
+                .remove(0);
+
+            let (cert_path, key_path) = (self.dir.join("cert.pem"), self.dir.join("key.pem"));
+            std::fs::write(&cert_path, pem).unwrap();
+            std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+            (cert_path, key_path, Certificate(der))
+        }
+
+        fn client(&self, cert: Option<(Certificate, PrivateKey)>) -> Arc<ClientConfig> {
+            let builder = ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(self.roots());
+            let config = match cert {
+                Some((cert, key)) => builder.with_client_auth_cert(vec![cert], key).unwrap(),
+                None => builder.with_no_client_auth(),
+            };
+            Arc::new(config)
+        }
+    }
+
+    impl Drop for Pki {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    async fn spawn_server(config: RustlsConfig) -> SocketAddr {
+        let app = Router::new().route("/", get(|| async { "hello" })).route(
+            "/peer",
+            get(|certs: PeerCertificates| async move { certs.len().to_string() }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            serve(listener, app.into_make_service())
+                .tls(config)
+                .into_future(),
+        );
+        addr
+    }
+
+    // the response body and the certificate the server presented
+    async fn request(
+        addr: SocketAddr,
+        client: Arc<ClientConfig>,
+        path: &str,
+    ) -> io::Result<(String, Certificate)> {
+        let stream = TcpStream::connect(addr).await?;
+        let domain = "localhost".try_into().unwrap();
+        let mut stream = TlsConnector::from(client).connect(domain, stream).await?;
+        let cert = stream.get_ref().1.peer_certificates().unwrap()[0].clone();
+
+        let req = format!(
+            "GET {} HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n",
+            path
+        );
+        stream.write_all(req.as_bytes()).await?;
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await?;
+
+        if !res.starts_with("HTTP/1.1 200 OK") {
+            return Err(io::Error::other(res));
+        }
+        let body = res.split("\r\n\r\n").nth(1).unwrap_or_default().to_owned();
+        Ok((body, cert))
+    }
+
+    #[tokio::test]
+    async fn handshake() {
+        let pki = Pki::new("handshake");
+        let (cert_path, key_path, cert) = pki.write_server_pem();
+        let addr = spawn_server(RustlsConfig::from_pem_file(cert_path, key_path).unwrap()).await;
+
+        let (body, server_cert) = request(addr, pki.client(None), "/").await.unwrap();
+        assert_eq!(body, "hello");
+        assert_eq!(server_cert, cert);
+    }
+
+    #[tokio::test]
+    async fn peer_certificates() {
+        let pki = Pki::new("peer-certificates");
+        let (cert_path, key_path, _) = pki.write_server_pem();
+
+        let config = RustlsConfig::from_pem_file_with_client_auth(
+            &cert_path,
+            &key_path,
+            ClientAuth::Optional(pki.roots()),
+        )
+        .unwrap();
+        let addr = spawn_server(config).await;
+
+        let client = pki.client(Some(pki.issue("client")));
+        let (body, _) = request(addr, client, "/peer").await.unwrap();
+        assert_eq!(body, "1");
+        let (body, _) = request(addr, pki.client(None), "/peer").await.unwrap();
+        assert_eq!(body, "0");
+
+        let config = RustlsConfig::from_pem_file_with_client_auth(
+            &cert_path,
+            &key_path,
+            ClientAuth::Required(pki.roots()),
+        )
+        .unwrap();
+        let addr = spawn_server(config).await;
+
+        let client = pki.client(Some(pki.issue("client")));
+        let (body, _) = request(addr, client, "/peer").await.unwrap();
+        assert_eq!(body, "1");
+        assert!(request(addr, pki.client(None), "/peer").await.is_err());
+
+        // signed by someone else
+        let other = Pki::new("peer-certificates-other");
+        let client = pki.client(Some(other.issue("client")));
+        assert!(request(addr, client, "/peer").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn reloads_changed_pem_files() {
+        let pki = Pki::new("reload");
+        let (cert_path, key_path, first) = pki.write_server_pem();
+        let config = RustlsConfig::from_pem_file(&cert_path, &key_path)
+            .unwrap()
+            .reload_interval(Duration::from_millis(20));
+        let addr = spawn_server(config).await;
+
+        let (_, cert) = request(addr, pki.client(None), "/").await.unwrap();
+        assert_eq!(cert, first);
+
+        // file systems with coarse timestamps need the modification time to
+        // move on
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let (_, _, second) = pki.write_server_pem();
+        assert_ne!(first, second);
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        loop {
+            let (_, cert) = request(addr, pki.client(None), "/").await.unwrap();
+            if cert == second {
+                break;
+            }
+            assert!(tokio::time::Instant::now() < deadline, "not reloaded");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn handshake_timeout() {
+        let pki = Pki::new("handshake-timeout");
+        let (cert_path, key_path, _) = pki.write_server_pem();
+        let config = RustlsConfig::from_pem_file(cert_path, key_path)
+            .unwrap()
+            .handshake_timeout(Duration::from_millis(50));
+        let addr = spawn_server(config).await;
+
+        // a client that never starts the handshake is disconnected
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut buf = Vec::new();
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut buf)).await;
+        assert!(matches!(read, Ok(Ok(0))), "{:?}", read);
+    }
+}
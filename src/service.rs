@@ -1,4 +1,21 @@
-use std::marker::PhantomData;
+use std::{
+    fmt,
+    marker::PhantomData,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use http::{Request, Response};
+use tower::{util::Oneshot, ServiceExt};
+use tower_service::Service;
+
+use self::future::HandleErrorFuture;
+use crate::{body::BoxBody, response::IntoResponse, BoxError};
+
+pub mod future;
+
+// Converts the errors of a fallible service into responses with `f`, so
+// middleware like timeouts can be used in front of handlers and routers.
 pub struct HandleError<S, F, B> {
     inner: S,
     f: F,
@@ -8,9 +25,118 @@ pub struct HandleError<S, F, B> {
 impl<S, F, B> HandleError<S, F, B> {
     pub(crate) fn new(inner: S, f: F) -> Self {
         Self {
-            inner: inner,
+            inner,
             f,
             _marker: PhantomData,
         }
     }
 }
+
+impl<S, F, B> Clone for HandleError<S, F, B>
+where
+    S: Clone,
+    F: Clone,
+{
+    fn clone(&self) -> Self {
+        Self::new(self.inner.clone(), self.f.clone())
+    }
+}
+
+impl<S, F, B> fmt::Debug for HandleError<S, F, B>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HandleError")
+            .field("inner", &self.inner)
+            .field("f", &format_args!("{}", std::any::type_name::<F>()))
+            .finish()
+    }
+}
+
+impl<S, F, ReqBody, ResBody, Res, E> Service<Request<ReqBody>> for HandleError<S, F, ReqBody>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone,
+    F: FnOnce(S::Error) -> Result<Res, E> + Clone,
+    Res: IntoResponse,
+    ResBody: http_body::Body<Data = Bytes> + Send + Sync + 'static,
+    ResBody::Error: Into<BoxError> + Send + Sync + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = E;
+    type Future = HandleErrorFuture<Oneshot<S, Request<ReqBody>>, F>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    // the inner service is driven to readiness by `oneshot`, so its
+    // readiness errors go through `f` as well
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        HandleErrorFuture {
+            inner: self.inner.clone().oneshot(req),
+            f: Some(self.f.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, time::Duration};
+
+    use http::StatusCode;
+    use hyper::Body;
+    use tower::timeout::{error::Elapsed, TimeoutLayer};
+
+    use super::*;
+    use crate::{
+        handler::{get, Handler},
+        Router,
+    };
+
+    fn handle_timeout(err: BoxError) -> Result<StatusCode, Infallible> {
+        if err.is::<Elapsed>() {
+            Ok(StatusCode::REQUEST_TIMEOUT)
+        } else {
+            Ok(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+
+    async fn slow() -> &'static str {
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        "too late"
+    }
+
+    #[tokio::test]
+    async fn timeout_maps_to_408() {
+        let app = Router::new().route(
+            "/",
+            get(slow
+                .layer(TimeoutLayer::new(Duration::from_millis(10)))
+                .handle_error(handle_timeout)),
+        );
+
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::REQUEST_TIMEOUT);
+    }
+
+    // `handle_error` turns a fallible service into one `Router::route_service`
+    // accepts, the router only takes services that can't fail
+    #[test]
+    fn handle_error_is_infallible() {
+        fn assert_infallible<S>(_: &S)
+        where
+            S: Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible>,
+        {
+        }
+
+        let svc = tower::ServiceBuilder::new()
+            .layer(TimeoutLayer::new(Duration::from_secs(1)))
+            .service(slow.into_service());
+        let svc = HandleError::new(svc, handle_timeout);
+        assert_infallible(&svc);
+
+        let _ = Router::new().route_service("/", svc);
+    }
+}
@@ -6,16 +6,12 @@ use std::{
 
 use bytes::Bytes;
 use futures_util::ready;
-use http::{Method, Request, Response};
-use http_body::Empty;
+use http::Response;
 use pin_project_lite::pin_project;
-use tower::util::Oneshot;
-use tower_service::Service;
 
 use crate::{
     body::{box_body, BoxBody},
     response::IntoResponse,
-    util::{Either, EitherProj},
     BoxError,
 };
 
@@ -54,47 +50,3 @@ where
         }
     }
 }
-
-pin_project! {
-    /// The response future for [`OnMethod`](super::OnMethod).
-    pub struct OnMethodFuture<S, F, B>
-    where
-        S: Service<Request<B>>,
-        F: Service<Request<B>>
-    {
-        #[pin]
-        pub(super) inner: Either<
-            Oneshot<S, Request<B>>,
-            Oneshot<F, Request<B>>,
-        >,
-        // pub(super) inner: crate::routing::future::RouteFuture<S, F, B>,
-        pub(super) req_method: Method,
-    }
-}
-
-impl<S, F, B, ResBody> Future for OnMethodFuture<S, F, B>
-where
-    S: Service<Request<B>, Response = Response<ResBody>> + Clone,
-    ResBody: http_body::Body<Data = Bytes> + Send + Sync + 'static,
-    ResBody::Error: Into<BoxError>,
-    F: Service<Request<B>, Response = Response<BoxBody>, Error = S::Error>,
-{
-    type Output = Result<Response<BoxBody>, S::Error>;
-
-    #[allow(warnings)]
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let this = self.project();
-
-        let response = match this.inner.project() {
-            EitherProj::A { inner } => ready!(inner.poll(cx))?.map(box_body),
-            EitherProj::B { inner } => ready!(inner.poll(cx))?,
-        };
-
-        if this.req_method == &Method::HEAD {
-            let response = response.map(|_| box_body(Empty::new()));
-            Poll::Ready(Ok(response))
-        } else {
-            Poll::Ready(Ok(response))
-        }
-    }
-}
@@ -29,6 +29,10 @@ impl ByteStr {
     {
         Self(Bytes::copy_from_slice(s.as_ref().as_bytes()))
     }
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
     pub(crate) fn as_str(&self) -> &str {
         std::str::from_utf8(&self.0).unwrap()
     }